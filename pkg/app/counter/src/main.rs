@@ -21,7 +21,7 @@ fn main() -> isize {
     }
 
     for i in 0..THREAD_COUNT {
-        let pid = sys_fork();
+        let pid = sys_fork().expect("Failed to fork");
         if pid == 0 {
             // do_counter_inc();
             if mode.trim() == "lock" {
@@ -47,7 +47,7 @@ fn main() -> isize {
 
     for i in 0..THREAD_COUNT {
         println!("#{} waiting for #{}...", cpid, pids[i]);
-        sys_wait_pid(pids[i]);
+        let _ = sys_wait_pid(pids[i]);
     }
 
     println!("COUNTER result: {}", unsafe { COUNTER });
@@ -83,7 +83,7 @@ fn main() -> isize {
     let mut pids = [0u16; N];
 
     for i in 0..N {
-        let pid = sys_fork();
+        let pid = sys_fork().expect("Failed to fork");
         if pid == 0 {
             philosopher(i);
             sys_exit(0);
@@ -93,7 +93,7 @@ fn main() -> isize {
     }
 
     for &pid in &pids {
-        sys_wait_pid(pid);
+        let _ = sys_wait_pid(pid);
     }
     0
 }
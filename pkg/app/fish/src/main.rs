@@ -48,7 +48,7 @@ fn main() -> isize {
     SEM_DONE.init(0);
 
     for child_fn in [proc_lt as fn() -> !, proc_gt, proc_us] {
-        let pid = sys_fork();
+        let pid = sys_fork().expect("Failed to fork");
         if pid == 0 {
             child_fn();
         }
@@ -62,13 +62,35 @@ fn main() -> isize {
      .join("\n");
     println!("{}", banner);
 
+    // Ctrl-C only clears the line in the shell
+    let _ = signal::set_handler(Signal::Int, SigHandler::Ignore);
+
     let mut cwd = "/".to_string();
+    // fds replaced by the redirections of the last command, with their saved copies
+    let mut saved_fds = Vec::new();
 
     loop {
+        restore_fds(core::mem::take(&mut saved_fds));
         print!("{}", prompt(&cwd));
 
         let line_buf = io::stdin().read_line();
-        let token: Vec<_> = line_buf.trim().split(' ').collect();
+        let (token, redirects) = match parse_redirects(line_buf.trim().split(' ').collect()) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                println!("{RED}{}{RESET}", err);
+                continue;
+            }
+        };
+        if token.is_empty() {
+            continue;
+        }
+        saved_fds = match redirect_fds(&cwd, &redirects) {
+            Ok(saved) => saved,
+            Err(err) => {
+                println!("{RED}{}{RESET}", err);
+                continue;
+            }
+        };
 
         match token[0] {
             "" => continue,
@@ -80,11 +102,14 @@ fn main() -> isize {
                 println!("  {GREEN}exit | quit{RESET} – exit shell");
                 println!("  {GREEN}lsapp{RESET}        – list applications");
                 println!("  {GREEN}ps{RESET}           – list processes");
-                println!("  {GREEN}exec <app>{RESET}   – execute <app>");
+                println!("  {GREEN}exec <app> [args]{RESET} – execute <app> with [args]");
+                println!("  {GREEN}kill <pid> [sig]{RESET} – send [sig] (default 15) to <pid>");
                 println!("  {GREEN}time{RESET}         – show current time");
                 println!("  {GREEN}ls <dir>{RESET}     – list files");
                 println!("  {GREEN}cwd{RESET}          – show cwd");
                 println!("  {GREEN}cd <dir>{RESET}     – change dir");
+                println!("  {GREEN}cat <file>{RESET}   – print file");
+                println!("{YELLOW}Redirections:{RESET} {GREEN}> file{RESET}, {GREEN}>> file{RESET}, {GREEN}< file{RESET}, {GREEN}2> file{RESET}");
             }
 
             "exit" | "quit" => {
@@ -93,11 +118,13 @@ fn main() -> isize {
             }
 
             "time" => {
-                let ts_ms = sys_time() as i128;
-                if ts_ms == 0 {
-                    println!("{RED}Failed to get time{RESET}");
-                    continue;
-                }
+                let ts_ms = match sys_time() {
+                    Ok(ts_ms) => ts_ms as i128,
+                    Err(err) => {
+                        println!("{RED}Failed to get time: {}{RESET}", err);
+                        continue;
+                    }
+                };
                 let beijing_ms = ts_ms + 8 * 60 * 60 * 1_000;
                 let secs = (beijing_ms / 1_000) as i64;
                 let nanos = ((beijing_ms % 1_000) as u32) * 1_000_000;
@@ -133,25 +160,51 @@ fn main() -> isize {
 
             "exec" => {
                 if token.len() < 2 {
-                    println!("{RED}Usage: exec <app_name>{RESET}");
+                    println!("{RED}Usage: exec <app_name> [args]{RESET}");
                     continue;
                 }
                 let app_name = token[1];
-                let ret = sys_wait_pid(sys_spawn(app_name));
-                if ret == -1 {
-                    println!("{RED}Failed to execute {}{RESET}", app_name);
+                let argv: Vec<&str> = token[1..].iter().copied().filter(|arg| !arg.is_empty()).collect();
+                if let Err(err) = sys_spawn_args(app_name, &argv, None).and_then(sys_wait_pid) {
+                    println!("{RED}Failed to execute {}: {}{RESET}", app_name, err);
+                }
+            }
+
+            "kill" => {
+                if token.len() < 2 {
+                    println!("{RED}Usage: kill <pid> [sig]{RESET}");
+                    continue;
+                }
+                let Ok(pid) = token[1].parse::<u16>() else {
+                    println!("{RED}Invalid pid:{RESET} {}", token[1]);
+                    continue;
+                };
+                let sig = match token.get(2) {
+                    None => Some(Signal::Term),
+                    Some(signo) => signo.parse::<usize>().ok().and_then(|n| Signal::try_from(n).ok()),
+                };
+                let Some(sig) = sig else {
+                    println!("{RED}Invalid signal:{RESET} {}", token[2]);
+                    continue;
+                };
+                if let Err(err) = signal::kill(pid, sig) {
+                    println!("{RED}Failed to kill {}: {}{RESET}", pid, err);
                 }
             }
 
             "ls" => {
                 if token.len() < 2 {
-                    sys_ls(&cwd);
+                    if let Err(err) = sys_ls(&cwd) {
+                        println!("{RED}Failed to list {}: {}{RESET}", cwd, err);
+                    }
                     continue;
                 }
                 let dir = token[1];
                 let path = make_abs_path(&cwd, dir);
                 if dir_exists(&path) {
-                    sys_list_dir(&path);
+                    if let Err(err) = sys_list_dir(&path) {
+                        println!("{RED}Failed to list {}: {}{RESET}", path, err);
+                    }
                 } else {
                     println!("{RED}Directory does not exist:{RESET} {}", path);
                 }
@@ -176,7 +229,17 @@ fn main() -> isize {
                     println!("{RED}Usage: cat <file_path>{RESET}");
                     continue;
                 }
-                sys_cat(token[1]);
+                let path = make_abs_path(&cwd, token[1]);
+                match fs::File::open(&path) {
+                    Ok(mut file) => {
+                        let mut buf = Vec::new();
+                        match file.read_to_end(&mut buf) {
+                            Ok(_) => println!("{}", String::from_utf8_lossy(&buf)),
+                            Err(err) => println!("{RED}Failed to read {}: {}{RESET}", path, err),
+                        }
+                    }
+                    Err(err) => println!("{RED}Cannot open {}: {}{RESET}", path, err),
+                }
             }
 
             unknown => {
@@ -187,6 +250,81 @@ fn main() -> isize {
     0
 }
 
+/// A redirection of `fd` to the file at `path`
+struct Redirect<'a> {
+    fd: u8,
+    path: &'a str,
+    flags: usize,
+}
+
+/// Split `>`, `>>`, `<` and `2>` redirections off the command tokens,
+/// the path may follow the operator directly or as the next token
+fn parse_redirects(token: Vec<&str>) -> Result<(Vec<&str>, Vec<Redirect<'_>>), String> {
+    const OPS: [(&str, u8, usize); 5] = [
+        ("2>>", 2, O_WRONLY | O_CREAT | O_APPEND),
+        ("2>", 2, O_WRONLY | O_CREAT | O_TRUNC),
+        (">>", 1, O_WRONLY | O_CREAT | O_APPEND),
+        (">", 1, O_WRONLY | O_CREAT | O_TRUNC),
+        ("<", 0, 0),
+    ];
+
+    let mut args = Vec::new();
+    let mut redirects = Vec::new();
+    let mut iter = token.into_iter();
+    while let Some(tok) = iter.next() {
+        let Some(&(op, fd, flags)) = OPS.iter().find(|(op, _, _)| tok.starts_with(op)) else {
+            args.push(tok);
+            continue;
+        };
+        let path = match &tok[op.len()..] {
+            "" => iter.find(|t| !t.is_empty()),
+            path => Some(path),
+        };
+        match path {
+            Some(path) => redirects.push(Redirect { fd, path, flags }),
+            None => return Err(format!("Missing file after {}", op)),
+        }
+    }
+
+    // keep `token[0]` valid for an empty line
+    if args.iter().all(|arg| arg.is_empty()) {
+        args.clear();
+    }
+    Ok((args, redirects))
+}
+
+/// Open the redirected files onto their fds, returns the replaced fds
+/// with their saved copies for `restore_fds`
+fn redirect_fds(cwd: &str, redirects: &[Redirect]) -> Result<Vec<(u8, u8)>, String> {
+    let mut saved = Vec::new();
+    for redirect in redirects {
+        let path = make_abs_path(cwd, redirect.path);
+        let ret = fs::File::open_with(&path, redirect.flags)
+            .map_err(|err| format!("Cannot open {}: {}", path, err))
+            .and_then(|file| {
+                // the saved copy is not passed on to spawned apps
+                let copy = sys_dup(redirect.fd, O_CLOEXEC)
+                    .map_err(|err| format!("Cannot redirect fd {}: {}", redirect.fd, err))?;
+                saved.push((redirect.fd, copy));
+                sys_dup2(file.fd(), redirect.fd, 0)
+                    .map_err(|err| format!("Cannot redirect fd {}: {}", redirect.fd, err))
+            });
+        if let Err(err) = ret {
+            restore_fds(saved);
+            return Err(err);
+        }
+    }
+    Ok(saved)
+}
+
+/// Move the saved copies back onto the fds replaced by `redirect_fds`
+fn restore_fds(saved: Vec<(u8, u8)>) {
+    for (fd, copy) in saved.into_iter().rev() {
+        let _ = sys_dup2(copy, fd, 0);
+        let _ = sys_close(copy);
+    }
+}
+
 fn make_abs_path(cwd: &str, raw: &str) -> String {
     let mut path = if raw.starts_with('/') {
         raw.to_string()
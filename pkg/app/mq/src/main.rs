@@ -99,7 +99,7 @@ fn main() -> isize {
     let mut pids = [0u16; CHILD_NUM];
 
     for i in 0..CHILD_NUM {
-        let pid = sys_fork();
+        let pid = sys_fork().expect("Failed to fork");
         if pid == 0 {
             let my_pid = sys_get_pid();
             if my_pid % 2 == 0 {
@@ -118,7 +118,7 @@ fn main() -> isize {
 
     for &cpid in &pids {
         println!("Parent waiting for child #{cpid}...");
-        sys_wait_pid(cpid);
+        let _ = sys_wait_pid(cpid);
     }
 
     println!("All children done, final QUEUE_LEN = {}", unsafe {
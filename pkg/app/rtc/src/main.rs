@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate lib;
+
+use core::arch::asm;
+use lib::*;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+fn read_cmos(reg: u8) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!("out dx, al", in("dx") CMOS_ADDRESS, in("al") reg);
+        asm!("in al, dx", in("dx") CMOS_DATA, out("al") value);
+    }
+    value
+}
+
+fn main() -> isize {
+    // the kernel starts us as a driver, so we may grant the ports to ourselves
+    if let Err(err) = sys_ioperm(sys_get_pid(), CMOS_ADDRESS, 2) {
+        errln!("rtc: failed to get the CMOS ports: {:?}", err);
+        return 1;
+    }
+
+    // wait for the RTC to finish updating its registers
+    while read_cmos(REG_STATUS_A) & 0x80 != 0 {}
+
+    let (mut hours, mut minutes, mut seconds) = (
+        read_cmos(REG_HOURS),
+        read_cmos(REG_MINUTES),
+        read_cmos(REG_SECONDS),
+    );
+
+    // the registers are in BCD unless bit 2 of status B is set
+    if read_cmos(REG_STATUS_B) & 0x04 == 0 {
+        let from_bcd = |v: u8| (v & 0x0f) + (v >> 4) * 10;
+        hours = from_bcd(hours);
+        minutes = from_bcd(minutes);
+        seconds = from_bcd(seconds);
+    }
+
+    println!("rtc: {:02}:{:02}:{:02} UTC", hours, minutes, seconds);
+
+    0
+}
+
+entry!(main);
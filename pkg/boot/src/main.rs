@@ -15,6 +15,7 @@ use uefi::mem::memory_map::MemoryMap;
 use uefi::proto::debug;
 use uefi::{Status, entry};
 use x86_64::registers::control::*;
+use x86_64::registers::model_specific::{Efer, EferFlags};
 use xmas_elf::ElfFile;
 use ysos_boot::config::Config;
 use ysos_boot::*;
@@ -67,6 +68,13 @@ fn efi_main() -> Status {
         });
     }
 
+    // non-executable pages need EFER.NXE, the bit is reserved otherwise
+    unsafe {
+        Efer::update(|flags| {
+            flags.insert(EferFlags::NO_EXECUTE_ENABLE);
+        });
+    }
+
     // FIXME: map physical memory to specific virtual address offset
     let mut frame_allocator = UEFIFrameAllocator;
 
@@ -108,7 +116,7 @@ fn efi_main() -> Status {
         &mut page_table,
         &mut frame_allocator,
         false,
-        false,
+        true,
     )
     .expect("Failed to map kernel stack");
 
@@ -99,6 +99,7 @@ pub fn map_range(
     page_table: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     user_access: bool,
+    no_execute: bool,
 ) -> Result<(), MapToError<Size4KiB>> {
     trace!("Mapping range: {:?}", page_range);
 
@@ -109,6 +110,11 @@ pub fn map_range(
     } else {
         flags
     };
+    let flags = if no_execute {
+        flags | PageTableFlags::NO_EXECUTE
+    } else {
+        flags
+    };
 
     for page in page_range {
         let frame = frame_allocator
@@ -153,6 +159,35 @@ pub fn load_elf(
         .collect()
 }
 
+/// Page table flags of an ELF segment
+///
+/// honors the segment R/W/X flags, writable segments are never executable (W^X)
+pub fn segment_flags(segment: &program::ProgramHeader, user_access: bool) -> PageTableFlags {
+    let mut page_table_flags = PageTableFlags::PRESENT;
+    let seg_flags = segment.flags();
+
+    if seg_flags.is_write() {
+        page_table_flags |= PageTableFlags::WRITABLE;
+    }
+
+    if seg_flags.is_execute() && seg_flags.is_write() {
+        warn!(
+            "Segment at {:#x} is writable and executable, mapping it non-executable",
+            segment.virtual_addr()
+        );
+    }
+
+    if !seg_flags.is_execute() || seg_flags.is_write() {
+        page_table_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    if user_access {
+        page_table_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+
+    page_table_flags
+}
+
 /// Load & Map ELF segment
 ///
 /// load segment to new frame and set page table
@@ -171,19 +206,9 @@ fn load_segment(
     let file_offset = segment.offset() & !0xfff;
     let virt_start_addr = VirtAddr::new(segment.virtual_addr());
 
-    let mut page_table_flags = PageTableFlags::PRESENT;
-
     // FIXME: handle page table flags with segment flags
     // unimplemented!("Handle page table flags with segment flags!");
-    let seg_flags = segment.flags();
-
-    if seg_flags.is_write() {
-        page_table_flags |= PageTableFlags::WRITABLE;
-    }
-
-    if user_access {
-        page_table_flags |= PageTableFlags::USER_ACCESSIBLE;
-    }
+    let page_table_flags = segment_flags(segment, user_access);
 
     trace!("Segment page table flag: {:?}", page_table_flags);
 
@@ -8,6 +8,7 @@ use chrono::DateTime;
 use storage::fat16::Fat16;
 use storage::mbr::*;
 use storage::*;
+use syscall_def::Errno;
 
 pub static ROOTFS: spin::Once<Mount> = spin::Once::new();
 
@@ -36,15 +37,28 @@ pub fn init() {
     info!("Initialized Filesystem.");
 }
 
-pub fn ls(root_path: &str) {
+/// Map a filesystem error to the errno reported to user space
+pub fn fs_errno(err: FsError) -> Errno {
+    match err {
+        FsError::FileNotFound => Errno::NotFound,
+        FsError::NotADirectory => Errno::NotADirectory,
+        FsError::NotAFile => Errno::IsADirectory,
+        FsError::ReadOnly => Errno::ReadOnly,
+        FsError::NotSupported => Errno::NotSupported,
+        FsError::InvalidOffset => Errno::InvalidArgument,
+        FsError::InvalidOperation => Errno::InvalidArgument,
+        FsError::FileNameError(_) | FsError::InvalidPath(_) => Errno::InvalidArgument,
+        FsError::WriteZero => Errno::NoSpace,
+        FsError::NotInSector
+        | FsError::EndOfFile
+        | FsError::BadCluster
+        | FsError::DeviceError(_) => Errno::IoError,
+    }
+}
+
+pub fn ls(root_path: &str) -> Result<()> {
     // info!("Listing files in '{}'", root_path);
-    let iter = match get_rootfs().read_dir(root_path) {
-        Ok(iter) => iter,
-        Err(err) => {
-            warn!("{:?}", err);
-            return;
-        }
-    };
+    let iter = get_rootfs().read_dir(root_path)?;
 
     // FIXME: format and print the file metadata
     //      - use `for meta in iter` to iterate over the entries
@@ -124,6 +138,8 @@ pub fn ls(root_path: &str) {
             size_w = size_w,
         );
     }
+
+    Ok(())
 }
 
 pub fn check_dir_exists(path: &str) -> bool {
@@ -133,8 +149,8 @@ pub fn check_dir_exists(path: &str) -> bool {
     }
 }
 
-pub fn cat(path: &str) -> Option<String> {
-    let mut file = get_rootfs().open_file(path).ok()?;
+pub fn cat(path: &str) -> Result<String> {
+    let mut file = get_rootfs().open_file(path)?;
 
     let mut buf = Vec::<u8>::new();
     if let Ok(meta) = get_rootfs().metadata(path) {
@@ -143,7 +159,7 @@ pub fn cat(path: &str) -> Option<String> {
 
     let mut tmp = [0u8; 512];
     loop {
-        let n = file.read(&mut tmp).ok()?;
+        let n = file.read(&mut tmp)?;
         if n == 0 {
             break; // EOF
         }
@@ -157,5 +173,5 @@ pub fn cat(path: &str) -> Option<String> {
 
     println!("{}", s);
 
-    Some(s)
+    Ok(s)
 }
@@ -29,7 +29,10 @@ pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
 // }
 
 pub extern "C" fn clock(mut context: ProcessContext) {
+    inc_counter();
+    wake_up_sleepers(crate::utils::clock::monotonic_ns());
     switch(&mut context);
+    handle_signals(&mut context);
     super::ack();
 }
 as_handler!(clock);
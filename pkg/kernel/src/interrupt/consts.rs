@@ -27,6 +27,7 @@ pub enum Interrupts {
 
     IrqBase = 0x20,
     Syscall = 0x80,
+    Yield = 0x81,
 }
 
 /// https://www.computerhope.com/jargon/i/irq.htm
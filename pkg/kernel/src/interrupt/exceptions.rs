@@ -1,5 +1,7 @@
 use crate::memory::*;
+use crate::proc::{ProcessContext, UserFault, uaccess};
 use alloc::format;
+use syscall_def::Signal;
 use x86_64::VirtAddr;
 use x86_64::registers::control::Cr2;
 use x86_64::registers::segmentation::SegmentSelector;
@@ -32,15 +34,57 @@ pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
     idt.non_maskable_interrupt
         .set_handler_fn(non_maskable_interrupt_handler);
     idt.overflow.set_handler_fn(overflow_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+}
+
+/// Report an exception raised in user mode to the current process with `sig`,
+/// an exception raised in the kernel is fatal
+fn fault(
+    context: &mut ProcessContext,
+    exception: &'static str,
+    sig: Signal,
+    err_code: Option<u64>,
+) {
+    if context.is_user() {
+        let fault = UserFault {
+            exception,
+            sig,
+            addr: None,
+            err_code,
+        };
+        crate::proc::raise_fault(&fault, context);
+        return;
+    }
 
-    // TODO: you should handle more exceptions here
-    // especially general protection fault (GPF)
-    // see: https://wiki.osdev.org/Exceptions
+    match err_code {
+        Some(err_code) => panic!(
+            "EXCEPTION: {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+            exception,
+            err_code,
+            context.value().stack_frame
+        ),
+        None => panic!(
+            "EXCEPTION: {}\n\n{:#?}",
+            exception,
+            context.value().stack_frame
+        ),
+    }
 }
 
-pub extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DIVIDE ERROR\n\n{:#?}", stack_frame);
+pub extern "C" fn divide_error(mut context: ProcessContext) {
+    fault(&mut context, "DIVIDE ERROR", Signal::Fpe, None);
 }
+as_handler!(divide_error);
+
+// double faults, machine checks, NMIs, invalid TSSs and HV injections
+// are not caused by the current process and stay fatal
 
 pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
@@ -52,82 +96,123 @@ pub extern "x86-interrupt" fn double_fault_handler(
     );
 }
 
-pub extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    err_code: PageFaultErrorCode,
-) {
+pub extern "C" fn page_fault(mut context: ProcessContext, err_code: u64) {
+    let err_code = PageFaultErrorCode::from_bits_truncate(err_code);
     let accessed_addr = match Cr2::read() {
         Ok(addr) => format!("{:#x}", addr.as_u64()),
         Err(e) => format!("Invalid Address: {:?}", e),
     };
 
-    if !crate::proc::handle_page_fault(Cr2::read().expect("REASON"), err_code) {
-        let proc_manager = crate::proc::manager::get_process_manager();
-        let curr_proc = proc_manager.current(); // <-- directly Arc<Process>
-
-        let pid = curr_proc.pid();
-        let proc_guard = curr_proc.read();
-        let name = proc_guard.name();
+    // the kernel touched user memory outside of `uaccess`
+    let violation = if context.is_user() {
+        None
+    } else {
+        uaccess::protection_violation(
+            Cr2::read_raw(),
+            err_code,
+            context.value().stack_frame.cpu_flags,
+        )
+    };
+    if let Some(protection) = violation {
+        let curr_proc = crate::proc::manager::get_process_manager().current();
         panic!(
-            "EXCEPTION: PAGE FAULT, ERROR_CODE: {:?}\n\nTrying to access: {}\nProcess {} ({}) caused the page fault.\n{:#?}",
-            err_code, accessed_addr, pid.0, name, stack_frame
+            "EXCEPTION: {} VIOLATION, ERROR_CODE: {:?}\n\nKernel accessed user memory at: {}\nProcess {} ({}) was running.\n{:#?}",
+            protection,
+            err_code,
+            accessed_addr,
+            curr_proc.pid().0,
+            curr_proc.read().name(),
+            context.value().stack_frame
         );
     }
-}
 
-pub extern "x86-interrupt" fn alignment_check_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: u64,
-) {
+    if crate::proc::handle_page_fault(Cr2::read().expect("REASON"), err_code, &mut context) {
+        return;
+    }
+
+    // bad accesses of a user process, including permission violations,
+    // are reported to it with SIGSEGV
+    if context.is_user() {
+        let fault = UserFault {
+            exception: "PAGE FAULT",
+            sig: Signal::Segv,
+            addr: Cr2::read().ok().map(|addr| addr.as_u64()),
+            err_code: Some(err_code.bits()),
+        };
+        crate::proc::raise_fault(&fault, &mut context);
+        return;
+    }
+
+    let proc_manager = crate::proc::manager::get_process_manager();
+    let curr_proc = proc_manager.current(); // <-- directly Arc<Process>
+
+    let pid = curr_proc.pid();
+    let proc_guard = curr_proc.read();
+    let name = proc_guard.name();
     panic!(
-        "EXCEPTION: ALIGNMENT CHECK, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: PAGE FAULT, ERROR_CODE: {:?}\n\nTrying to access: {}\nProcess {} ({}) caused the page fault.\n{:#?}",
+        err_code,
+        accessed_addr,
+        pid.0,
+        name,
+        context.value().stack_frame
     );
 }
+as_handler_with_err!(page_fault, PageFaultErrorCode);
 
-pub extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: BOUND RANGE EXCEEDED\n\n{:#?}", stack_frame);
+pub extern "C" fn alignment_check(mut context: ProcessContext, err_code: u64) {
+    fault(&mut context, "ALIGNMENT CHECK", Signal::Bus, Some(err_code));
 }
+as_handler_with_err!(alignment_check, u64);
 
-pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: BREAKPOINT\n\n{:#?}", stack_frame);
+pub extern "C" fn bound_range_exceeded(mut context: ProcessContext) {
+    fault(&mut context, "BOUND RANGE EXCEEDED", Signal::Segv, None);
 }
+as_handler!(bound_range_exceeded);
 
-pub extern "x86-interrupt" fn cp_protection_exception_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: u64,
-) {
-    panic!(
-        "EXCEPTION: COPROCESSOR PROTECTION EXCEPTION, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+pub extern "C" fn breakpoint(mut context: ProcessContext) {
+    fault(&mut context, "BREAKPOINT", Signal::Trap, None);
+}
+as_handler!(breakpoint);
+
+pub extern "C" fn cp_protection_exception(mut context: ProcessContext, err_code: u64) {
+    fault(
+        &mut context,
+        "COPROCESSOR PROTECTION EXCEPTION",
+        Signal::Segv,
+        Some(err_code),
     );
 }
+as_handler_with_err!(cp_protection_exception, u64);
 
-pub extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DEBUG\n\n{:#?}", stack_frame);
+pub extern "C" fn debug(mut context: ProcessContext) {
+    fault(&mut context, "DEBUG", Signal::Trap, None);
 }
+as_handler!(debug);
 
-pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DEVICE NOT AVAILABLE\n\n{:#?}", stack_frame);
+pub extern "C" fn device_not_available(mut context: ProcessContext) {
+    fault(&mut context, "DEVICE NOT AVAILABLE", Signal::Fpe, None);
 }
+as_handler!(device_not_available);
 
-pub extern "x86-interrupt" fn general_protection_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: u64,
-) {
-    panic!(
-        "EXCEPTION: GENERAL PROTECTION FAULT, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+pub extern "C" fn general_protection_fault(mut context: ProcessContext, err_code: u64) {
+    fault(
+        &mut context,
+        "GENERAL PROTECTION FAULT",
+        Signal::Segv,
+        Some(err_code),
     );
 }
+as_handler_with_err!(general_protection_fault, u64);
 
 pub extern "x86-interrupt" fn hv_injection_exception_handler(stack_frame: InterruptStackFrame) {
     panic!("EXCEPTION: HV INJECTION EXCEPTION\n\n{:#?}", stack_frame);
 }
 
-pub extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: INVALID OPCODE\n\n{:#?}", stack_frame);
+pub extern "C" fn invalid_opcode(mut context: ProcessContext) {
+    fault(&mut context, "INVALID OPCODE", Signal::Ill, None);
 }
+as_handler!(invalid_opcode);
 
 pub extern "x86-interrupt" fn invalid_tss_handler(
     stack_frame: InterruptStackFrame,
@@ -147,6 +232,37 @@ pub extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: Interr
     panic!("EXCEPTION: NON MASKABLE INTERRUPT\n\n{:#?}", stack_frame);
 }
 
-pub extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: OVERFLOW\n\n{:#?}", stack_frame);
+pub extern "C" fn overflow(mut context: ProcessContext) {
+    fault(&mut context, "OVERFLOW", Signal::Segv, None);
+}
+as_handler!(overflow);
+
+pub extern "C" fn segment_not_present(mut context: ProcessContext, err_code: u64) {
+    fault(
+        &mut context,
+        "SEGMENT NOT PRESENT",
+        Signal::Bus,
+        Some(err_code),
+    );
+}
+as_handler_with_err!(segment_not_present, u64);
+
+pub extern "C" fn stack_segment_fault(mut context: ProcessContext, err_code: u64) {
+    fault(
+        &mut context,
+        "STACK SEGMENT FAULT",
+        Signal::Bus,
+        Some(err_code),
+    );
+}
+as_handler_with_err!(stack_segment_fault, u64);
+
+pub extern "C" fn x87_floating_point(mut context: ProcessContext) {
+    fault(&mut context, "X87 FLOATING POINT", Signal::Fpe, None);
+}
+as_handler!(x87_floating_point);
+
+pub extern "C" fn simd_floating_point(mut context: ProcessContext) {
+    fault(&mut context, "SIMD FLOATING POINT", Signal::Fpe, None);
 }
+as_handler!(simd_floating_point);
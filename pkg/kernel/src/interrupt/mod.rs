@@ -26,6 +26,7 @@ lazy_static! {
 /// init interrupts system
 pub fn init() {
     IDT.load();
+    syscall::init();
 
     // FIXME: check and init APIC
     if XApic::support() {
@@ -1,8 +1,11 @@
-use crate::memory::gdt::SYSCALL_IST_INDEX;
 use crate::proc;
 use crate::proc::manager::get_process_manager;
 use crate::{memory::gdt, proc::*};
 use alloc::format;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
 // NOTE: import `ysos_syscall` package as `syscall_def` in Cargo.toml
@@ -16,21 +19,153 @@ use service::*;
 
 pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
     // FIXME: register syscall handler to IDT
-    //        - standalone syscall stack
+    //        - on the kernel stack of the process, from the TSS
     //        - ring 3
     idt[consts::Interrupts::Syscall as u8]
         .set_handler_fn(syscall_handler)
-        .set_stack_index(SYSCALL_IST_INDEX)
         .set_privilege_level(x86_64::PrivilegeLevel::Ring3);
+
+    // only raised by the kernel, see `yield_cpu`
+    idt[consts::Interrupts::Yield as u8].set_handler_fn(kernel_yield_handler);
+}
+
+/// Selectors of the user segments, pushed by `syscall_entry`
+static USER_CODE: AtomicU64 = AtomicU64::new(0);
+static USER_DATA: AtomicU64 = AtomicU64::new(0);
+
+/// The user stack pointer while `syscall_entry` switches stacks
+static mut USER_STACK: u64 = 0;
+
+/// Enter `syscall_entry` on the `syscall` instruction
+///
+/// Interrupts are disabled on entry as through the interrupt gate.
+pub fn init() {
+    let kernel = gdt::get_selector();
+    let user = gdt::get_user_selector();
+
+    Star::write(
+        user.code_selector,
+        user.data_selector,
+        kernel.code_selector,
+        kernel.data_selector,
+    )
+    .expect("Invalid STAR segment selectors");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+
+    USER_CODE.store(user.code_selector.0 as u64, Ordering::Relaxed);
+    USER_DATA.store(user.data_selector.0 as u64, Ordering::Relaxed);
+
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+
+    info!("Syscall Entry Initialized.");
 }
 
 pub extern "C" fn syscall(mut context: ProcessContext) {
+    handle_syscall(&mut context);
+}
+
+as_handler!(syscall);
+
+/// Like `syscall`, returns whether `syscall_entry` may leave with `sysretq`
+pub extern "C" fn fast_syscall(mut context: ProcessContext) -> bool {
+    handle_syscall(&mut context);
+    context.can_sysret()
+}
+
+fn handle_syscall(context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        super::syscall::dispatcher(&mut context);
+        uaccess::deny_user_access();
+        super::syscall::dispatcher(context);
+        proc::handle_signals(context);
     });
 }
 
-as_handler!(syscall);
+/// Entry of the `syscall` instruction
+///
+/// Switches to the kernel stack of the process and builds the interrupt
+/// stack frame the CPU pushes for `int 0x80`, so the registers and the frame
+/// form a `ProcessContext` like in `as_handler`.
+#[naked]
+pub extern "C" fn syscall_entry() {
+    unsafe {
+        core::arch::naked_asm!("
+        mov [rip + {user_stack}], rsp
+        mov rsp, [rip + {kernel_stack}]
+        push qword ptr [rip + {user_data}]
+        push qword ptr [rip + {user_stack}]
+        push r11
+        push qword ptr [rip + {user_code}]
+        push rcx
+        push rbp
+        push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15
+        call {handler}
+        test al, al
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax
+        pop rbp
+        jz 2f
+        mov rsp, [rsp + 24]
+        sysretq
+    2:
+        iretq",
+        user_stack = sym USER_STACK,
+        kernel_stack = sym gdt::KERNEL_STACK_TOP,
+        user_data = sym USER_DATA,
+        user_code = sym USER_CODE,
+        handler = sym fast_syscall);
+    }
+}
+
+pub extern "C" fn kernel_yield(mut context: ProcessContext) {
+    proc::block_current(&mut context);
+}
+
+as_handler!(kernel_yield);
+
+/// Give up the CPU in the middle of a syscall until the process is woken up
+///
+/// The kernel context of the process is saved on its kernel stack by the
+/// interrupt, and it returns from here once the process is switched back.
+pub fn yield_cpu() {
+    unsafe {
+        core::arch::asm!(
+            "int {vector}",
+            vector = const consts::Interrupts::Yield as u8,
+            clobber_abi("C"),
+        )
+    };
+}
 
 #[derive(Clone, Debug)]
 pub struct SyscallArgs {
@@ -53,45 +188,82 @@ pub fn dispatcher(context: &mut ProcessContext) {
 
     match args.syscall {
         // fd: arg0 as u8, buf: &[u8] (ptr: arg1 as *const u8, len: arg2)
-        Syscall::Read => context.set_rax(sys_read(&args)),
+        Syscall::Read => sys_read(&args, context),
         // fd: arg0 as u8, buf: &[u8] (ptr: arg1 as *const u8, len: arg2)
-        Syscall::Write => context.set_rax(sys_write(&args)),
+        Syscall::Write => sys_write(&args, context),
+        // fds: arg0 as *mut [u8; 2] (read end, write end) -> None
+        Syscall::Pipe => context.set_result(sys_pipe(&args)),
+
+        // path: &str (ptr: arg0 as *const u8, len: arg1), flags: arg2 (O_*) -> fd: u8
+        Syscall::Open => context.set_result(sys_open(&args)),
+        // fd: arg0 as u8 -> None
+        Syscall::Close => context.set_result(sys_close(&args)),
+        // fd: arg0 as u8, flags: arg1 (0 or O_CLOEXEC) -> new fd: u8 (lowest free)
+        Syscall::Dup => context.set_result(sys_dup(&args)),
+        // old: arg0 as u8, new: arg1 as u8, flags: arg2 (0 or O_CLOEXEC) -> new fd: u8
+        Syscall::Dup2 => context.set_result(sys_dup2(&args)),
+        // fd: arg0 as u8, offset: arg1 as isize, whence: arg2 (0: start, 1: current, 2: end)
+        //     -> offset: usize
+        Syscall::Seek => context.set_result(sys_seek(&args)),
 
         // None -> time: u64
-        Syscall::Time => context.set_rax(sys_time()),
+        Syscall::Time => context.set_result(sys_time()),
+        // clock: arg0 (0: realtime, 1: monotonic) -> ns: u64
+        Syscall::ClockGetTime => context.set_result(sys_clock_gettime(&args)),
 
-        Syscall::Brk => context.set_rax(sys_brk(&args)),
+        // addr: arg0 (0: query) -> heap_end: usize
+        Syscall::Brk => context.set_result(sys_brk(&args)),
+        // args: arg0 as *const MmapArgs -> addr: usize
+        Syscall::Mmap => context.set_result(sys_mmap(&args)),
+        // addr: arg0 (page aligned), len: arg1 -> None
+        Syscall::Munmap => context.set_result(sys_munmap(&args)),
+        // addr: arg0 (page aligned), len: arg1, prot: arg2 (PROT_*) -> None
+        Syscall::Mprotect => context.set_result(sys_mprotect(&args)),
+
+        // ns: arg0 as u64 -> None
+        Syscall::Sleep => sys_sleep(&args, context),
 
         // None -> pid: u16
-        Syscall::GetPid => context.set_rax(sys_get_pid()),
+        Syscall::GetPid => context.set_result(sys_get_pid()),
 
         // None -> pid: u16
         Syscall::Fork => sys_fork(context),
 
-        // path: &str (ptr: arg0 as *const u8, len: arg1) -> pid: u16
-        Syscall::Spawn => context.set_rax(spawn_process(&args)),
+        // path: &str (ptr: arg0 as *const u8, len: arg1),
+        //     args: arg2 as *const ExecArgs (0: no arguments) -> pid: u16
+        Syscall::Spawn => context.set_result(spawn_process(&args)),
+        // path: &str (ptr: arg0 as *const u8, len: arg1),
+        //     args: arg2 as *const ExecArgs (0: no arguments) -> only returns on error
+        Syscall::Exec => sys_exec(&args, context),
         // ret: arg0 as isize
         Syscall::Exit => exit_process(&args, context),
-        // pid: arg0 as u16 -> status: isize
+        // pid: arg0 as isize (-1: any child), status: arg1 as *mut isize,
+        //     options: arg2 (WNOHANG) -> pid: u16 (0: no child exited yet)
         Syscall::WaitPid => sys_wait_pid(&args, context),
 
-        // op: u8, key: u32, val: usize -> ret: any
+        // op: u8, key: u32, val: usize -> None
         Syscall::Sem => sys_sem(&args, context),
 
-        // path: &str (arg0 as *const u8, arg1 as len)
-        Syscall::ListDir => list_dir(&args),
+        // pid: arg0 as u16, sig: arg1 (0: only check the pid) -> None
+        Syscall::Kill => context.set_result(sys_kill(&args)),
+        // pid: arg0 as u16, from: arg1 (first port), count: arg2 -> None
+        Syscall::IoPerm => context.set_result(sys_ioperm(&args)),
+        // sig: arg0, handler: arg1 (0: default, 1: ignore), trampoline: arg2
+        //     -> old handler: usize
+        Syscall::SigAction => context.set_result(sys_sigaction(&args)),
+        // how: arg0 (0: block, 1: unblock, 2: set), set: arg1 as u64 -> old set: u64
+        Syscall::SigProcMask => context.set_result(sys_sigprocmask(&args)),
+        // frame: arg0 as *const SignalFrame -> does not return to the caller
+        Syscall::SigReturn => sys_sigreturn(&args, context),
+
+        // path: &str (arg0 as *const u8, arg1 as len) -> None
+        Syscall::ListDir => context.set_result(list_dir(&args)),
 
         // path: &str (arg0 as *const u8, arg1 as len) -> exists: bool
-        Syscall::Exists => context.set_rax(sys_exists(&args)),
-
-        // path: &str (arg0 as *const u8, arg1 as len) -> content: String
-        Syscall::Cat => {
-            if let Some(content) = sys_cat(&args) {
-                context.set_rax(content.len() as usize);
-            } else {
-                context.set_rax(0);
-            }
-        }
+        Syscall::Exists => context.set_result(sys_exists(&args)),
+
+        // path: &str (arg0 as *const u8, arg1 as len) -> len: usize
+        Syscall::Cat => context.set_result(sys_cat(&args)),
 
         // None
         Syscall::Stat => {
@@ -102,16 +274,11 @@ pub fn dispatcher(context: &mut ProcessContext) {
             list_app();
         }
 
-        // ----------------------------------------------------
-        // NOTE: following syscall examples are implemented
-        // ----------------------------------------------------
-
-        // layout: arg0 as *const Layout -> ptr: *mut u8
-        Syscall::Allocate => context.set_rax(sys_allocate(&args)),
-        // ptr: arg0 as *mut u8
-        Syscall::Deallocate => sys_deallocate(&args),
         // Unknown
-        Syscall::Unknown => warn!("Unhandled syscall: {:x?}", context.regs.rax),
+        Syscall::Unknown => {
+            warn!("Unhandled syscall: {:x?}", context.regs.rax);
+            context.set_result(Err(syscall_def::Errno::NoSys));
+        }
     }
 }
 
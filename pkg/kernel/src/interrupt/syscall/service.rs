@@ -1,111 +1,260 @@
 use alloc::sync::Arc;
-use core::alloc::Layout;
+use storage::SeekFrom;
 use storage::fat16::file;
+use syscall_def::signal::SigMaskHow;
+use syscall_def::{
+    ARG_MAX, ClockId, Errno, ExecArgs, MmapArgs, O_CLOEXEC, SigSet, Signal, SyscallResult,
+};
 
-use crate::drivers::filesystem;
-use crate::interrupt::clock::current_time_fixed;
+use crate::drivers::filesystem::{self, fs_errno};
 use crate::proc::manager::get_process_manager;
+use crate::proc::uaccess::*;
 use crate::proc::*;
+use crate::utils::clock;
 use crate::utils::*;
 use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use x86_64::VirtAddr;
 
 use super::SyscallArgs;
 
-use chrono::Timelike;
+/// The largest chunk of user data buffered in the kernel at once
+const IO_CHUNK_SIZE: usize = 0x1000;
 
-pub fn spawn_process(args: &SyscallArgs) -> usize {
+pub fn spawn_process(args: &SyscallArgs) -> SyscallResult {
     // FIXME: get app name by args
     //       - core::str::from_utf8_unchecked
     //       - core::slice::from_raw_parts
     // FIXME: spawn the process by name
     // FIXME: handle spawn error, return 0 if failed
     // FIXME: return pid as usize
-    let buf = unsafe { core::slice::from_raw_parts(args.arg0 as *const u8, args.arg1 as usize) };
-    let name = unsafe { core::str::from_utf8_unchecked(buf) };
-    let pid = crate::proc::spawn(name);
-    if pid.is_none() {
-        return 0;
+    let name = copy_str_from_user(args.arg0, args.arg1).ok_or(Errno::Fault)?;
+    let (argv, envp) = copy_exec_args(args.arg2)?;
+    let pid = crate::proc::spawn(&name, argv, envp)?;
+
+    Ok(pid.0 as usize)
+}
+
+pub fn sys_exec(args: &SyscallArgs, context: &mut ProcessContext) {
+    let ret = copy_str_from_user(args.arg0, args.arg1)
+        .ok_or(Errno::Fault)
+        .and_then(|name| {
+            let (argv, envp) = copy_exec_args(args.arg2)?;
+            exec(&name, argv, envp, context)
+        });
+
+    // on success the context already belongs to the new program
+    if let Err(errno) = ret {
+        context.set_result(Err(errno));
+    }
+}
+
+/// Copy the `ExecArgs` at `addr` from user space, if any
+fn copy_exec_args(addr: usize) -> Result<(Vec<String>, Option<Vec<String>>), Errno> {
+    if addr == 0 {
+        return Ok((Vec::new(), None));
     }
 
-    pid.unwrap().0 as usize
+    let buf = copy_from_user(addr, size_of::<ExecArgs>()).ok_or(Errno::Fault)?;
+    let exec_args = unsafe { (buf.as_ptr() as *const ExecArgs).read_unaligned() };
+
+    let copy_strs = |ptr: usize, len: usize| {
+        if len > ARG_MAX {
+            return Err(Errno::ArgListTooLong);
+        }
+        copy_strs_from_user(ptr, len).ok_or(Errno::Fault)
+    };
+
+    let argv = match exec_args.argv_ptr {
+        0 => Vec::new(),
+        ptr => copy_strs(ptr, exec_args.argv_len)?,
+    };
+    let envp = match exec_args.envp_ptr {
+        0 => None,
+        ptr => Some(copy_strs(ptr, exec_args.envp_len)?),
+    };
+
+    Ok((argv, envp))
 }
 
-pub fn sys_read(args: &SyscallArgs) -> usize {
+/// Run a read or write and set its result
+///
+/// Blocks in place while a pipe is not ready, and sends SIGPIPE for a broken one.
+fn set_io_result(fd: u8, io: impl Fn() -> SyscallResult, context: &mut ProcessContext) {
+    let ret = loop {
+        match io() {
+            Err(Errno::WouldBlock) => {
+                if let Err(errno) = wait_resource(fd) {
+                    break Err(errno);
+                }
+            }
+            ret => break ret,
+        }
+    };
+
+    match ret {
+        Err(Errno::BrokenPipe) => {
+            context.set_result(ret);
+            let pid = get_process_manager().current().pid();
+            let _ = send_signal(pid, Some(Signal::Pipe));
+        }
+        ret => context.set_result(ret),
+    }
+}
+
+pub fn sys_read(args: &SyscallArgs, context: &mut ProcessContext) {
+    set_io_result(args.arg0 as u8, || read_user(args), context);
+}
+
+pub fn sys_write(args: &SyscallArgs, context: &mut ProcessContext) {
+    set_io_result(args.arg0 as u8, || write_user(args), context);
+}
+
+fn read_user(args: &SyscallArgs) -> SyscallResult {
     // FIXME: just like sys_write
     let fd = args.arg0 as u8;
-    let buf = unsafe { core::slice::from_raw_parts_mut(args.arg1 as *mut u8, args.arg2 as usize) };
 
-    crate::proc::read(fd, buf) as usize
+    // short reads are fine, never buffer more than one chunk in the kernel,
+    // and only check the part of the user buffer that may be written
+    let len = args.arg2.min(IO_CHUNK_SIZE);
+    if !check_user_range(args.arg1, len, true) {
+        return Err(Errno::Fault);
+    }
+
+    let mut buf = vec![0u8; len];
+    let ret = crate::proc::read(fd, &mut buf)?;
+
+    if ret > 0 && !copy_to_user(args.arg1, &buf[..ret]) {
+        return Err(Errno::Fault);
+    }
+
+    Ok(ret)
 }
 
-pub fn sys_write(args: &SyscallArgs) -> usize {
+fn write_user(args: &SyscallArgs) -> SyscallResult {
     // FIXME: get buffer and fd by args
     //       - core::slice::from_raw_parts
     // FIXME: call proc::write -> isize
     // FIXME: return the result as usize
     let fd = args.arg0 as u8;
-    let buf = unsafe { core::slice::from_raw_parts(args.arg1 as *const u8, args.arg2 as usize) };
+    if !check_user_range(args.arg1, args.arg2, false) {
+        return Err(Errno::Fault);
+    }
 
-    crate::proc::write(fd, buf) as usize
-}
+    let mut written = 0;
+    while written < args.arg2 {
+        let len = (args.arg2 - written).min(IO_CHUNK_SIZE);
+        let buf = copy_from_user(args.arg1 + written, len).ok_or(Errno::Fault)?;
 
-pub fn sys_time() -> usize {
-    current_time_fixed().map_or(0, |dt| {
-        (dt.timestamp() as u128 * 1000 + dt.time().nanosecond() as u128 / 1_000_000) as usize
-    })
-}
+        let ret = match crate::proc::write(fd, &buf) {
+            Ok(ret) => ret,
+            // report the error only if nothing has been written
+            Err(errno) if written == 0 => return Err(errno),
+            Err(_) => break,
+        };
 
-pub fn exit_process(args: &SyscallArgs, context: &mut ProcessContext) {
-    // FIXME: exit process with retcode
-    exit(args.arg0 as isize, context);
+        written += ret;
+        if ret < len {
+            break;
+        }
+    }
+
+    Ok(written)
 }
 
-pub fn list_process() {
-    // FIXME: list all processes
-    print_process_list();
+pub fn sys_pipe(args: &SyscallArgs) -> SyscallResult {
+    // fail before any fd is opened
+    if !check_user_range(args.arg0, 2, true) {
+        return Err(Errno::Fault);
+    }
+
+    let (read_fd, write_fd) = pipe()?;
+    copy_to_user(args.arg0, &[read_fd, write_fd]);
+    Ok(0)
 }
 
-pub fn sys_allocate(args: &SyscallArgs) -> usize {
-    let layout = unsafe { (args.arg0 as *const Layout).as_ref().unwrap() };
+pub fn sys_open(args: &SyscallArgs) -> SyscallResult {
+    let path = copy_str_from_user(args.arg0, args.arg1).ok_or(Errno::Fault)?;
+
+    open(&path, args.arg2).map(|fd| fd as usize)
+}
 
-    if layout.size() == 0 {
-        return 0;
+pub fn sys_dup(args: &SyscallArgs) -> SyscallResult {
+    if args.arg1 & !O_CLOEXEC != 0 {
+        return Err(Errno::InvalidArgument);
     }
 
-    let ret = crate::memory::user::USER_ALLOCATOR
-        .lock()
-        .allocate_first_fit(*layout);
+    dup(args.arg0 as u8, args.arg1 != 0).map(|fd| fd as usize)
+}
 
-    match ret {
-        Ok(ptr) => ptr.as_ptr() as usize,
-        Err(_) => 0,
+pub fn sys_dup2(args: &SyscallArgs) -> SyscallResult {
+    if args.arg2 & !O_CLOEXEC != 0 {
+        return Err(Errno::InvalidArgument);
     }
+
+    dup2(args.arg0 as u8, args.arg1 as u8, args.arg2 != 0).map(|fd| fd as usize)
 }
 
-pub fn sys_deallocate(args: &SyscallArgs) {
-    let layout = unsafe { (args.arg1 as *const Layout).as_ref().unwrap() };
+pub fn sys_close(args: &SyscallArgs) -> SyscallResult {
+    close(args.arg0 as u8).map(|_| 0)
+}
 
-    if args.arg0 == 0 || layout.size() == 0 {
-        return;
-    }
+pub fn sys_seek(args: &SyscallArgs) -> SyscallResult {
+    let fd = args.arg0 as u8;
+    let offset = args.arg1 as isize;
 
-    let ptr = args.arg0 as *mut u8;
+    let pos = match args.arg2 {
+        0 if offset >= 0 => SeekFrom::Start(offset as usize),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(Errno::InvalidArgument),
+    };
 
-    unsafe {
-        crate::memory::user::USER_ALLOCATOR
-            .lock()
-            .deallocate(core::ptr::NonNull::new_unchecked(ptr), *layout);
-    }
+    seek(fd, pos)
 }
 
-pub fn sys_get_pid() -> usize {
-    get_process_manager().current().pid().0 as usize
+pub fn sys_time() -> SyscallResult {
+    Ok((clock::realtime_ns() / 1_000_000) as usize)
+}
+
+pub fn sys_clock_gettime(args: &SyscallArgs) -> SyscallResult {
+    let ns = match ClockId::try_from(args.arg0).map_err(|_| Errno::InvalidArgument)? {
+        ClockId::Realtime => clock::realtime_ns(),
+        ClockId::Monotonic => clock::monotonic_ns(),
+    };
+
+    Ok(ns as usize)
+}
+
+pub fn exit_process(args: &SyscallArgs, context: &mut ProcessContext) {
+    // FIXME: exit process with retcode
+    exit(args.arg0 as isize, context);
+}
+
+pub fn list_process() {
+    // FIXME: list all processes
+    print_process_list();
+}
+
+pub fn sys_get_pid() -> SyscallResult {
+    Ok(get_process_manager().current().pid().0 as usize)
 }
 
 pub fn sys_wait_pid(args: &SyscallArgs, context: &mut ProcessContext) {
-    let pid = args.arg0 as u16;
-    wait_pid(pid, context);
+    let pid = match args.arg0 as isize {
+        syscall_def::WAIT_ANY => None,
+        pid => match u16::try_from(pid) {
+            Ok(pid) => Some(ProcessId(pid)),
+            Err(_) => return context.set_result(Err(Errno::InvalidArgument)),
+        },
+    };
+
+    wait_pid(pid, args.arg1, args.arg2, context);
+}
+
+pub fn sys_sleep(args: &SyscallArgs, context: &mut ProcessContext) {
+    sleep(args.arg0 as u64, context);
 }
 
 pub fn sys_fork(context: &mut ProcessContext) {
@@ -114,61 +263,98 @@ pub fn sys_fork(context: &mut ProcessContext) {
 
 pub fn sys_sem(args: &SyscallArgs, context: &mut ProcessContext) {
     match args.arg0 {
-        0 => context.set_rax(new_sem(args.arg1 as u32, args.arg2)),
-        1 => context.set_rax(remove_sem(args.arg1 as u32)),
+        0 => context.set_result(new_sem(args.arg1 as u32, args.arg2).map(|_| 0)),
+        1 => context.set_result(remove_sem(args.arg1 as u32).map(|_| 0)),
         2 => sem_signal(args.arg1 as u32, context),
         3 => sem_wait(args.arg1 as u32, context),
-        _ => context.set_rax(usize::MAX),
+        _ => context.set_result(Err(Errno::InvalidArgument)),
     }
 }
 
-pub fn list_dir(args: &SyscallArgs) {
-    let root_dir = unsafe {
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-            args.arg0 as *const u8,
-            args.arg1 as usize,
-        ))
-    };
-    filesystem::ls(root_dir);
+pub fn list_dir(args: &SyscallArgs) -> SyscallResult {
+    let root_dir = copy_str_from_user(args.arg0, args.arg1).ok_or(Errno::Fault)?;
+
+    filesystem::ls(&root_dir).map_err(fs_errno)?;
+    Ok(0)
 }
 
-pub fn sys_exists(args: &SyscallArgs) -> usize {
-    let path = unsafe {
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-            args.arg0 as *const u8,
-            args.arg1 as usize,
-        ))
-    };
-    if filesystem::check_dir_exists(path) {
-        1 // exists
-    } else {
-        0 // does not exist
-    }
+pub fn sys_exists(args: &SyscallArgs) -> SyscallResult {
+    let path = copy_str_from_user(args.arg0, args.arg1).ok_or(Errno::Fault)?;
+
+    Ok(filesystem::check_dir_exists(&path) as usize)
 }
 
-pub fn sys_cat(args: &SyscallArgs) -> Option<String> {
-    let path = unsafe {
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-            args.arg0 as *const u8,
-            args.arg1 as usize,
-        ))
-    };
+pub fn sys_cat(args: &SyscallArgs) -> SyscallResult {
+    let path = copy_str_from_user(args.arg0, args.arg1).ok_or(Errno::Fault)?;
+
+    filesystem::cat(&path)
+        .map(|content| content.len())
+        .map_err(fs_errno)
+}
 
-    filesystem::cat(path)
+pub fn sys_mmap(args: &SyscallArgs) -> SyscallResult {
+    let buf = copy_from_user(args.arg0, size_of::<MmapArgs>()).ok_or(Errno::Fault)?;
+    let mmap_args = unsafe { (buf.as_ptr() as *const MmapArgs).read_unaligned() };
+
+    mmap(&mmap_args)
+}
+
+pub fn sys_munmap(args: &SyscallArgs) -> SyscallResult {
+    munmap(args.arg0, args.arg1).map(|_| 0)
 }
 
-pub fn sys_brk(args: &SyscallArgs) -> usize {
+pub fn sys_mprotect(args: &SyscallArgs) -> SyscallResult {
+    mprotect(args.arg0, args.arg1, args.arg2).map(|_| 0)
+}
+
+pub fn sys_brk(args: &SyscallArgs) -> SyscallResult {
     let new_heap_end = if args.arg0 == 0 {
         None
     } else {
-        Some(VirtAddr::new(args.arg0 as u64))
+        Some(VirtAddr::try_new(args.arg0 as u64).map_err(|_| Errno::InvalidArgument)?)
     };
     match brk(new_heap_end) {
         Some(new_heap_end) => {
             debug!("New heap end: {:#x}", new_heap_end);
-            new_heap_end.as_u64() as usize},
+            Ok(new_heap_end.as_u64() as usize)
+        }
         None => {
             debug!("Failed to set new heap end");
-            !0},
+            Err(Errno::NoMemory)
+        }
     }
 }
+
+pub fn sys_ioperm(args: &SyscallArgs) -> SyscallResult {
+    let pid = u16::try_from(args.arg0).map_err(|_| Errno::NoProcess)?;
+    grant_io_ports(ProcessId(pid), args.arg1, args.arg2)?;
+    Ok(0)
+}
+
+pub fn sys_kill(args: &SyscallArgs) -> SyscallResult {
+    let pid = u16::try_from(args.arg0).map_err(|_| Errno::NoProcess)?;
+
+    // signal 0 only checks that the process can be signaled
+    let sig = match args.arg1 {
+        0 => None,
+        signo => Some(Signal::try_from(signo).map_err(|_| Errno::InvalidArgument)?),
+    };
+
+    send_signal(ProcessId(pid), sig)?;
+    Ok(0)
+}
+
+pub fn sys_sigaction(args: &SyscallArgs) -> SyscallResult {
+    let sig = Signal::try_from(args.arg0).map_err(|_| Errno::InvalidArgument)?;
+    sigaction(sig, args.arg1, args.arg2)
+}
+
+pub fn sys_sigprocmask(args: &SyscallArgs) -> SyscallResult {
+    let how = SigMaskHow::try_from(args.arg0).map_err(|_| Errno::InvalidArgument)?;
+    let old = sigprocmask(how, SigSet::from_bits(args.arg1 as u64));
+    Ok(old.bits() as usize)
+}
+
+pub fn sys_sigreturn(args: &SyscallArgs, context: &mut ProcessContext) {
+    sigreturn(args.arg0, context);
+}
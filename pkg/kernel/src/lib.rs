@@ -48,6 +48,9 @@ pub fn init(boot_info: &'static BootInfo) {
     memory::gdt::init(); // init gdt
     memory::allocator::init(); // init kernel heap allocator
     interrupt::init(); // init interrupts
+    proc::uaccess::init(); // enable SMEP / SMAP
+    proc::fpu::init(); // enable SSE / AVX for user mode
+    utils::clock::init(); // init clocksource
     memory::init(boot_info); // init memory manager
     proc::init(boot_info); // init process manager
 
@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 
+use alloc::vec::Vec;
 use log::*;
 use storage::Block;
 use storage::FsError;
@@ -21,16 +22,29 @@ pub fn kernel_main(boot_info: &'static boot::BootInfo) -> ! {
     ysos::init(boot_info);
     drive_init();
     filesystem::init();
+    spawn_drivers();
     ysos::wait(spawn_init());
     ysos::shutdown();
 }
 
+/// Apps started as drivers at boot, they may grant themselves I/O ports
+const DRIVERS: &[&str] = &["rtc"];
+
+pub fn spawn_drivers() {
+    for &name in DRIVERS {
+        match proc::spawn_driver(name, Vec::new()) {
+            Ok(pid) => info!("Driver {} started as process #{}", name, pid),
+            Err(err) => warn!("Failed to start driver {}: {:?}", name, err),
+        }
+    }
+}
+
 pub fn spawn_init() -> proc::ProcessId {
     // NOTE: you may want to clear the screen before starting the shell
     // print_serial!("\x1b[1;1H\x1b[2J");
 
     proc::list_app();
-    proc::spawn("fwsh").unwrap()
+    proc::spawn("fwsh", Vec::new(), None).unwrap()
 }
 
 pub fn drive_init() {
@@ -1,35 +1,246 @@
-use core::ptr::addr_of_mut;
-use linked_list_allocator::LockedHeap;
-use x86_64::VirtAddr;
+//! Kernel heap
+//!
+//! Allocations up to 2 KiB are served by the slab caches, larger ones by a
+//! `linked_list_allocator` heap in a dedicated virtual range, which maps more
+//! frames at its top when it runs out. A small static heap in `.bss` serves
+//! the allocations made before the frame allocator is up, and the ones made
+//! while the frame allocator is locked and the heap cannot grow.
 
-pub const HEAP_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{NonNull, addr_of_mut, null_mut};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+};
+use x86_64::{VirtAddr, align_up};
+
+use super::slab::{SIZE_CLASSES, SlabCache, SlabUsage, size_class, slab_layout};
+use super::{PAGE_SIZE, PHYSICAL_OFFSET, get_frame_alloc, physical_to_virtual};
+
+pub const BOOTSTRAP_HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
+
+// 0x4000_0000 bytes -> 1GiB
+// from 0xffff_ff40_0000_0000 to 0xffff_ff40_3fff_ffff
+pub const KERNEL_HEAP_START: u64 = 0xffff_ff40_0000_0000;
+pub const KERNEL_HEAP_MAX_SIZE: u64 = 0x4000_0000;
+pub const KERNEL_HEAP_END: u64 = KERNEL_HEAP_START + KERNEL_HEAP_MAX_SIZE;
+
+/// The heap grows by at least this many bytes at a time
+const GROW_SIZE: u64 = 256 * 1024; // 256 KiB
+
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
 
-/// Use linked_list_allocator for kernel heap
 #[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: KernelAllocator = KernelAllocator::new();
+
+pub struct KernelAllocator {
+    bootstrap: Mutex<Heap>,
+    heap: Mutex<Heap>,
+    caches: [Mutex<SlabCache>; SIZE_CLASSES.len()],
+}
+
+/// Bytes used and available in a heap
+#[derive(Clone, Copy, Debug)]
+pub struct HeapUsage {
+    pub used: usize,
+    pub size: usize,
+}
+
+impl KernelAllocator {
+    const fn new() -> Self {
+        Self {
+            bootstrap: Mutex::new(Heap::empty()),
+            heap: Mutex::new(Heap::empty()),
+            caches: [const { Mutex::new(SlabCache::new()) }; SIZE_CLASSES.len()],
+        }
+    }
+
+    /// Usage of the static bootstrap heap
+    pub fn bootstrap_usage(&self) -> HeapUsage {
+        let heap = self.bootstrap.lock();
+        HeapUsage {
+            used: heap.used(),
+            size: heap.size(),
+        }
+    }
+
+    /// Usage of the growable heap, `size` is the mapped part of its range
+    pub fn heap_usage(&self) -> HeapUsage {
+        let heap = self.heap.lock();
+        HeapUsage {
+            used: heap.used(),
+            size: heap.size(),
+        }
+    }
+
+    /// Statistics of each slab cache
+    pub fn slab_usage(&self) -> [SlabUsage; SIZE_CLASSES.len()] {
+        core::array::from_fn(|class| self.caches[class].lock().usage(SIZE_CLASSES[class]))
+    }
+
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        {
+            let mut heap = self.heap.lock();
+            if heap.size() > 0 {
+                let ptr = match heap.allocate_first_fit(layout) {
+                    Ok(ptr) => Ok(ptr),
+                    Err(()) if grow(&mut heap, (layout.size() + layout.align()) as u64) => {
+                        heap.allocate_first_fit(layout)
+                    }
+                    Err(()) => Err(()),
+                };
+                if let Ok(ptr) = ptr {
+                    return Some(ptr);
+                }
+            }
+        }
+
+        self.bootstrap.lock().allocate_first_fit(layout).ok()
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut bootstrap = self.bootstrap.lock();
+        if (bootstrap.bottom()..bootstrap.top()).contains(&ptr.as_ptr()) {
+            unsafe { bootstrap.deallocate(ptr, layout) };
+        } else {
+            drop(bootstrap);
+            unsafe { self.heap.lock().deallocate(ptr, layout) };
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = match size_class(&layout) {
+            Some(class) => self.caches[class]
+                .lock()
+                .allocate(SIZE_CLASSES[class], || self.allocate(slab_layout())),
+            None => self.allocate(layout),
+        };
+
+        ptr.map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(ptr) = NonNull::new(ptr) else {
+            return;
+        };
+
+        match size_class(&layout) {
+            Some(class) => unsafe { self.caches[class].lock().deallocate(ptr) },
+            None => unsafe { self.deallocate(ptr, layout) },
+        }
+    }
+}
+
+/// Map at least `size` more bytes at the top of `heap`
+fn grow(heap: &mut Heap, size: u64) -> bool {
+    let size = align_up(size.max(GROW_SIZE), PAGE_SIZE);
+    let top = heap.top() as u64;
+
+    if top + size > KERNEL_HEAP_END {
+        return false;
+    }
+
+    let mapped = map_heap(top, size);
+    if mapped > 0 {
+        unsafe { heap.extend(mapped as usize) };
+    }
+
+    mapped == size
+}
+
+/// Back `[start, start + size)` of the heap range with frames,
+/// returns the number of bytes mapped from `start`
+///
+/// The frame allocator is only tried, it is locked when the kernel allocates
+/// while mapping frames, the allocation then falls back to the bootstrap heap.
+fn map_heap(start: u64, size: u64) -> u64 {
+    let Some(mut alloc) = get_frame_alloc() else {
+        return 0;
+    };
+
+    // a PageTableContext would allocate, the current page table is used as is,
+    // the heap range is shared by all of them
+    let (frame, _) = Cr3::read();
+    let mut mapper = unsafe {
+        OffsetPageTable::new(
+            &mut *(physical_to_virtual(frame.start_address().as_u64()) as *mut PageTable),
+            VirtAddr::new_truncate(*PHYSICAL_OFFSET.get().unwrap()),
+        )
+    };
+
+    let pages = Page::range(
+        Page::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(start + size)),
+    );
+
+    let mut mapped = 0;
+    for page in pages {
+        let Some(frame) = alloc.allocate_frame() else {
+            break;
+        };
+
+        match unsafe { mapper.map_to(page, frame, HEAP_FLAGS, &mut *alloc) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { alloc.deallocate_frames(frame, 0) };
+                break;
+            }
+        }
+        mapped += PAGE_SIZE;
+    }
+
+    mapped
+}
 
 pub fn init() {
-    // static buffer for kernel heap
+    // static buffer for the bootstrap heap
     // will be allocated on the bss section when the kernel is load
-    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+    static mut HEAP: [u8; BOOTSTRAP_HEAP_SIZE] = [0; BOOTSTRAP_HEAP_SIZE];
 
     let heap_start = VirtAddr::from_ptr(addr_of_mut!(HEAP));
-    let heap_end = heap_start + HEAP_SIZE as u64;
+    let heap_end = heap_start + BOOTSTRAP_HEAP_SIZE as u64;
 
     unsafe {
         ALLOCATOR
+            .bootstrap
             .lock()
-            .init(addr_of_mut!(HEAP) as *mut u8, HEAP_SIZE);
+            .init(addr_of_mut!(HEAP) as *mut u8, BOOTSTRAP_HEAP_SIZE);
     }
 
     debug!(
-        "Kernel Heap      : 0x{:016x}-0x{:016x}",
+        "Bootstrap Heap   : 0x{:016x}-0x{:016x}",
         heap_start.as_u64(),
         heap_end.as_u64()
     );
 
-    let (size, unit) = crate::humanized_size(HEAP_SIZE as u64);
-    info!("Kernel Heap Size : {:>7.*} {}", 3, size, unit);
+    let (size, unit) = crate::humanized_size(BOOTSTRAP_HEAP_SIZE as u64);
+    info!("Bootstrap Heap Size : {:>7.*} {}", 3, size, unit);
+
+    info!("Bootstrap Heap Initialized.");
+}
+
+// NOTE: call in the kernel init / after frame allocator
+pub fn init_heap() {
+    let mapped = map_heap(KERNEL_HEAP_START, GROW_SIZE);
+    assert_eq!(mapped, GROW_SIZE, "Kernel Heap Initialization Failed.");
+
+    unsafe {
+        ALLOCATOR
+            .heap
+            .lock()
+            .init(KERNEL_HEAP_START as *mut u8, mapped as usize);
+    }
+
+    debug!(
+        "Kernel Heap      : 0x{:016x}-0x{:016x}",
+        KERNEL_HEAP_START, KERNEL_HEAP_END
+    );
 
     info!("Kernel Heap Initialized.");
 }
@@ -1,10 +1,15 @@
-use crate::humanized_size;
-use crate::memory::PAGE_SIZE;
-use alloc::boxed::Box;
-use alloc::vec::Vec;
+//! Physical frame allocator
+//!
+//! A buddy allocator over the usable regions of the memory map. Free blocks
+//! of 2^order frames are kept in one list per order, linked through the free
+//! frames themselves, and a freed block is merged with its buddy when both
+//! are free. The state of each frame is kept in frames taken from the usable
+//! memory at init, so the allocator never uses the kernel heap.
+
+use super::{PAGE_SIZE, physical_to_virtual};
 use boot::{MemoryMap, MemoryType};
 use x86_64::PhysAddr;
-use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB, Size4KiB};
 
 once_mutex!(pub FRAME_ALLOCATOR: BootInfoFrameAllocator);
 
@@ -12,14 +17,63 @@ guard_access_fn! {
     pub get_frame_alloc(FRAME_ALLOCATOR: BootInfoFrameAllocator)
 }
 
-type BootInfoFrameIter = Box<dyn Iterator<Item = PhysFrame> + Send>;
+/// The largest block is 2^MAX_ORDER frames (4 MiB)
+pub const MAX_ORDER: usize = 10;
+
+/// Order of a 2 MiB huge page
+pub const HUGE_PAGE_ORDER: usize = 9;
+
+/// Memory types whose frames are managed by the allocator
+///
+/// Boot services memory is left alone, it still holds the page tables
+/// set up by the firmware.
+const USABLE_TYPES: [MemoryType; 1] = [MemoryType::CONVENTIONAL];
+
+/// End of a free list
+const NIL: u64 = u64::MAX;
+
+/// `FrameInfo::order` of a frame that does not start a free block,
+/// `FrameInfo::ty` of a frame that is not managed
+const NONE: u8 = u8::MAX;
+
+/// State of a frame
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct FrameInfo {
+    /// order of the free block starting at the frame, or `NONE`
+    order: u8,
+    /// index of the memory type of the frame in `USABLE_TYPES`, or `NONE`
+    ty: u8,
+    /// mappings of an allocated frame besides the first one
+    shares: u16,
+}
+
+/// Links of a free list, stored in the first frame of a free block
+#[repr(C)]
+struct FreeBlock {
+    prev: u64,
+    next: u64,
+}
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
+/// Frames of a memory type managed by the allocator
+#[derive(Clone, Copy, Debug)]
+pub struct TypeUsage {
+    pub ty: MemoryType,
+    pub used: usize,
+    pub total: usize,
+}
+
+/// A buddy FrameAllocator over the usable frames of the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
+    /// frame number of `info[0]`
+    base: u64,
+    /// state of the frames from `base` to the last usable frame
+    info: &'static mut [FrameInfo],
+    /// first block of the free list of each order
+    free: [u64; MAX_ORDER + 1],
     size: usize,
     used: usize,
-    frames: BootInfoFrameIter,
-    recycled: Vec<PhysFrame>,
+    usage: [TypeUsage; USABLE_TYPES.len()],
 }
 
 impl BootInfoFrameAllocator {
@@ -28,13 +82,81 @@ impl BootInfoFrameAllocator {
     /// This function is unsafe because the caller must guarantee that the passed
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &MemoryMap, size: usize) -> Self {
-        BootInfoFrameAllocator {
-            size,
-            frames: create_frame_iter(memory_map),
+    pub unsafe fn init(memory_map: &MemoryMap) -> Self {
+        let usable = || {
+            memory_map.iter().filter_map(|r| {
+                let ty = USABLE_TYPES.iter().position(|&ty| ty == r.ty)?;
+                let start = r.phys_start / PAGE_SIZE;
+                (r.page_count > 0).then_some((start, start + r.page_count, ty))
+            })
+        };
+
+        let base = usable()
+            .map(|(start, _, _)| start)
+            .min()
+            .expect("No usable memory");
+        let end = usable().map(|(_, end, _)| end).max().unwrap();
+
+        // the frame states are stored at the start of the first region that can hold them
+        let info_len = (end - base) as usize;
+        let info_frames = (info_len * size_of::<FrameInfo>()).div_ceil(PAGE_SIZE as usize) as u64;
+        let (info_start, _, _) = usable()
+            .find(|(start, end, _)| end - start >= info_frames)
+            .expect("No room for the frame allocator");
+
+        let info = unsafe {
+            core::slice::from_raw_parts_mut(
+                physical_to_virtual(info_start * PAGE_SIZE) as *mut FrameInfo,
+                info_len,
+            )
+        };
+        info.fill(FrameInfo {
+            order: NONE,
+            ty: NONE,
+            shares: 0,
+        });
+
+        let mut allocator = BootInfoFrameAllocator {
+            base,
+            info,
+            free: [NIL; MAX_ORDER + 1],
+            size: 0,
             used: 0,
-            recycled: Vec::with_capacity(size),
+            usage: USABLE_TYPES.map(|ty| TypeUsage {
+                ty,
+                used: 0,
+                total: 0,
+            }),
+        };
+
+        for (start, end, ty) in usable() {
+            let start = if start == info_start {
+                start + info_frames
+            } else {
+                start
+            };
+
+            for pfn in start..end {
+                allocator.info[(pfn - base) as usize].ty = ty as u8;
+            }
+
+            let count = end.saturating_sub(start) as usize;
+            allocator.size += count;
+            allocator.usage[ty].total += count;
+
+            // split the region into the largest aligned blocks
+            let mut pfn = start;
+            while pfn < end {
+                let order = (0..=MAX_ORDER)
+                    .rev()
+                    .find(|&order| pfn % (1 << order) == 0 && pfn + (1 << order) <= end)
+                    .unwrap();
+                allocator.insert_block(pfn, order);
+                pfn += 1 << order;
+            }
         }
+
+        allocator
     }
 
     pub fn frames_used(&self) -> usize {
@@ -45,40 +167,187 @@ impl BootInfoFrameAllocator {
         self.size
     }
 
-    pub fn recycled_size(&self) -> usize {
-        self.recycled.len()
+    /// Frames used and managed for each memory type
+    pub fn usage(&self) -> &[TypeUsage] {
+        &self.usage
+    }
+
+    /// Allocate 2^order contiguous frames, the first one is aligned to the block size
+    pub fn allocate_frames(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let mut current = (order..=MAX_ORDER).find(|&order| self.free[order] != NIL)?;
+        let pfn = self.free[current];
+        self.remove_block(pfn, current);
+
+        // return the upper halves to the free lists
+        while current > order {
+            current -= 1;
+            self.push_block(pfn + (1 << current), current);
+        }
+
+        self.account(pfn, order, true);
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            pfn * PAGE_SIZE,
+        )))
+    }
+
+    /// Free 2^order frames allocated with `allocate_frames`
+    ///
+    /// # Safety
+    ///
+    /// The frames must not be used anymore.
+    pub unsafe fn deallocate_frames(&mut self, frame: PhysFrame, order: usize) {
+        let pfn = frame.start_address().as_u64() / PAGE_SIZE;
+
+        match self.frame_info(pfn) {
+            Some(info) if info.ty != NONE && info.order == NONE => {}
+            _ => {
+                warn!(
+                    "Freeing a frame that is not allocated: {:#x}",
+                    frame.start_address().as_u64()
+                );
+                return;
+            }
+        }
+
+        self.account(pfn, order, false);
+        self.insert_block(pfn, order);
+    }
+
+    /// Record another mapping of `frame`,
+    /// deallocating it only drops a mapping until the last one is gone
+    pub fn share_frame(&mut self, frame: PhysFrame) {
+        let pfn = frame.start_address().as_u64() / PAGE_SIZE;
+        if let Some(info) = self.frame_info_mut(pfn) {
+            info.shares += 1;
+        }
+    }
+
+    /// Number of mappings of an allocated frame
+    pub fn frame_refs(&self, frame: PhysFrame) -> usize {
+        let pfn = frame.start_address().as_u64() / PAGE_SIZE;
+        self.frame_info(pfn)
+            .map_or(1, |info| info.shares as usize + 1)
+    }
+
+    fn frame_info(&self, pfn: u64) -> Option<&FrameInfo> {
+        self.info.get(pfn.checked_sub(self.base)? as usize)
+    }
+
+    fn frame_info_mut(&mut self, pfn: u64) -> Option<&mut FrameInfo> {
+        self.info.get_mut(pfn.checked_sub(self.base)? as usize)
     }
+
+    /// Update the usage counters for the block of 2^order frames at `pfn`
+    fn account(&mut self, pfn: u64, order: usize, allocated: bool) {
+        for pfn in pfn..pfn + (1 << order) {
+            let ty = self.info[(pfn - self.base) as usize].ty as usize;
+            if allocated {
+                self.usage[ty].used += 1;
+            } else {
+                self.usage[ty].used -= 1;
+            }
+        }
+
+        if allocated {
+            self.used += 1 << order;
+        } else {
+            self.used -= 1 << order;
+        }
+    }
+
+    /// Free the block at `pfn`, merged with its buddy as long as it is free
+    fn insert_block(&mut self, mut pfn: u64, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = pfn ^ (1 << order);
+            if self
+                .frame_info(buddy)
+                .is_none_or(|info| info.order != order as u8)
+            {
+                break;
+            }
+
+            self.remove_block(buddy, order);
+            pfn = pfn.min(buddy);
+            order += 1;
+        }
+
+        self.push_block(pfn, order);
+    }
+
+    fn push_block(&mut self, pfn: u64, order: usize) {
+        let head = self.free[order];
+
+        unsafe {
+            block(pfn).write(FreeBlock {
+                prev: NIL,
+                next: head,
+            });
+            if head != NIL {
+                (*block(head)).prev = pfn;
+            }
+        }
+
+        self.free[order] = pfn;
+        self.info[(pfn - self.base) as usize].order = order as u8;
+    }
+
+    fn remove_block(&mut self, pfn: u64, order: usize) {
+        let FreeBlock { prev, next } = unsafe { block(pfn).read() };
+
+        unsafe {
+            if prev != NIL {
+                (*block(prev)).next = next;
+            } else {
+                self.free[order] = next;
+            }
+            if next != NIL {
+                (*block(next)).prev = prev;
+            }
+        }
+
+        self.info[(pfn - self.base) as usize].order = NONE;
+    }
+}
+
+/// The free list links of the free block at `pfn`
+#[inline]
+fn block(pfn: u64) -> *mut FreeBlock {
+    physical_to_virtual(pfn * PAGE_SIZE) as *mut FreeBlock
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        self.used += 1;
-        if let Some(frame) = self.recycled.pop() {
-            Some(frame)
-        } else {
-            self.frames.next()
-        }
+        self.allocate_frames(0)
     }
 }
 
 impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
-    unsafe fn deallocate_frame(&mut self, _frame: PhysFrame) {
-        // TODO: deallocate frame (not for lab 2)
-        self.used -= 1;
-        self.recycled.push(_frame);
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        // a shared frame is kept for its other mappings
+        let pfn = frame.start_address().as_u64() / PAGE_SIZE;
+        if let Some(info) = self.frame_info_mut(pfn).filter(|info| info.shares > 0) {
+            info.shares -= 1;
+            return;
+        }
+
+        unsafe { self.deallocate_frames(frame, 0) };
     }
 }
 
-fn create_frame_iter(memory_map: &MemoryMap) -> BootInfoFrameIter {
-    let iter = memory_map
-        .clone()
-        .into_iter()
-        // get usable regions from memory map
-        .filter(|r| r.ty == MemoryType::CONVENTIONAL)
-        // align to page boundary
-        .flat_map(|r| (0..r.page_count).map(move |v| (v * 4096 + r.phys_start)))
-        // create `PhysFrame` types from the start addresses
-        .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
-
-    Box::new(iter)
+unsafe impl FrameAllocator<Size2MiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate_frames(HUGE_PAGE_ORDER)?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        unsafe { self.deallocate_frames(frame, HUGE_PAGE_ORDER) };
+    }
 }
@@ -1,4 +1,10 @@
-use core::ptr::addr_of_mut;
+use alloc::boxed::Box;
+use alloc::vec;
+use bit_field::BitField;
+use core::mem::size_of;
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use x86_64::VirtAddr;
 use x86_64::registers::segmentation::Segment;
@@ -8,87 +14,104 @@ use x86_64::structures::tss::TaskStateSegment;
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 pub const CLOCK_IST_INDEX: u16 = 2;
-pub const SYSCALL_IST_INDEX: u16 = 3;
 
-pub const IST_SIZES: [usize; 5] = [0x1000, 0x1000, 0x1000, 0x1000, 0x1000];
+pub const IST_SIZES: [usize; 4] = [0x1000, 0x1000, 0x1000, 0x1000];
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-
-        // initialize the TSS with the static buffers
-        // will be allocated on the bss section when the kernel is load
-        //
-        // DO NOT MODIFY THE FOLLOWING CODE
-        tss.privilege_stack_table[0] = {
-            const STACK_SIZE: usize = IST_SIZES[0];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
-            info!(
-                "Privilege Stack  : 0x{:016x}-0x{:016x}",
-                stack_start.as_u64(),
-                stack_end.as_u64()
-            );
-            stack_end
-        };
-
-        // FIXME: fill tss.interrupt_stack_table with the static stack buffers like above
-        // You can use `tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize]`
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[1];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
-            info!(
-                "Double Fault Stack: 0x{:016x}-0x{:016x}",
-                stack_start.as_u64(),
-                stack_end.as_u64()
-            );
-            stack_end
-        };
-
-        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[2];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
-            info!(
-                "Page Fault Stack  : 0x{:016x}-0x{:016x}",
-                stack_start.as_u64(),
-                stack_end.as_u64()
-            );
-            stack_end
-        };
-
-        tss.interrupt_stack_table[CLOCK_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[3];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
-            info!(
-                "Clock Stack       : 0x{:016x}-0x{:016x}",
-                stack_start.as_u64(),
-                stack_end.as_u64()
-            );
-            stack_end
-        };
-
-        tss.interrupt_stack_table[SYSCALL_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[4];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
-            info!(
-                "Syscall Stack      : 0x{:016x}-0x{:016x}",
-                stack_start.as_u64(),
-                stack_end.as_u64()
-            );
-            stack_end
-        };
-
-        tss
+/// Number of I/O ports
+pub const IO_PORTS: u32 = 0x10000;
+
+/// Size of an I/O permission bitmap, one bit for each port
+pub const IO_BITMAP_SIZE: usize = IO_PORTS as usize / 8;
+
+/// The TSS followed by its I/O permission bitmap
+///
+/// The bitmap holds the ports of the running process, the extra byte ends it.
+#[repr(C)]
+struct TaskState {
+    tss: TaskStateSegment,
+    io_bitmap: [u8; IO_BITMAP_SIZE + 1],
+}
+
+static mut TASK_STATE: TaskState = TaskState {
+    tss: TaskStateSegment::new(),
+    io_bitmap: [0xff; IO_BITMAP_SIZE + 1],
+};
+
+/// Whether the bitmap of the TSS allows some ports
+static IO_BITMAP_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// `TASK_STATE.tss.privilege_stack_table[0]`, for the `syscall` entry that
+/// does not switch stacks by itself
+pub static KERNEL_STACK_TOP: AtomicU64 = AtomicU64::new(0);
+
+/// Fill in the TSS in `TASK_STATE`, before its descriptor is built
+///
+/// The TSS is only accessed through raw pointers afterwards, as the stacks
+/// and the I/O bitmap are updated on every process switch.
+fn init_tss() {
+    let mut tss = TaskStateSegment::new();
+
+    // initialize the TSS with the static buffers
+    // will be allocated on the bss section when the kernel is load
+    //
+    // DO NOT MODIFY THE FOLLOWING CODE
+    tss.privilege_stack_table[0] = {
+        const STACK_SIZE: usize = IST_SIZES[0];
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
+        let stack_end = stack_start + STACK_SIZE as u64;
+        info!(
+            "Privilege Stack  : 0x{:016x}-0x{:016x}",
+            stack_start.as_u64(),
+            stack_end.as_u64()
+        );
+        stack_end
+    };
+
+    // FIXME: fill tss.interrupt_stack_table with the static stack buffers like above
+    // You can use `tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize]`
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = IST_SIZES[1];
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
+        let stack_end = stack_start + STACK_SIZE as u64;
+        info!(
+            "Double Fault Stack: 0x{:016x}-0x{:016x}",
+            stack_start.as_u64(),
+            stack_end.as_u64()
+        );
+        stack_end
     };
+
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = IST_SIZES[2];
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
+        let stack_end = stack_start + STACK_SIZE as u64;
+        info!(
+            "Page Fault Stack  : 0x{:016x}-0x{:016x}",
+            stack_start.as_u64(),
+            stack_end.as_u64()
+        );
+        stack_end
+    };
+
+    tss.interrupt_stack_table[CLOCK_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = IST_SIZES[3];
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
+        let stack_end = stack_start + STACK_SIZE as u64;
+        info!(
+            "Clock Stack       : 0x{:016x}-0x{:016x}",
+            stack_start.as_u64(),
+            stack_end.as_u64()
+        );
+        stack_end
+    };
+
+    tss.iomap_base = core::mem::offset_of!(TaskState, io_bitmap) as u16;
+
+    unsafe { addr_of_mut!(TASK_STATE.tss).write(tss) };
 }
 
 lazy_static! {
@@ -96,9 +119,10 @@ lazy_static! {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        let user_code_selector = gdt.append(Descriptor::user_code_segment());
+        let tss_selector = gdt.append(tss_descriptor());
+        // `sysretq` expects the user data segment right before the user code
         let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
         (
             gdt,
             KernelSelectors {
@@ -114,6 +138,29 @@ lazy_static! {
     };
 }
 
+/// The TSS descriptor, with a limit covering the I/O permission bitmap
+fn tss_descriptor() -> Descriptor {
+    use x86_64::structures::gdt::DescriptorFlags;
+
+    init_tss();
+
+    let ptr = addr_of!(TASK_STATE) as u64;
+
+    let mut low = DescriptorFlags::PRESENT.bits();
+    // base
+    low.set_bits(16..40, ptr.get_bits(0..24));
+    low.set_bits(56..64, ptr.get_bits(24..32));
+    // limit (the `-1` in needed since the bound is inclusive)
+    low.set_bits(0..16, (size_of::<TaskState>() - 1) as u64);
+    // type (0b1001 = available 64-bit tss)
+    low.set_bits(40..44, 0b1001);
+
+    let mut high = 0;
+    high.set_bits(0..32, ptr.get_bits(32..64));
+
+    Descriptor::SystemSegment(low, high)
+}
+
 #[derive(Debug)]
 pub struct KernelSelectors {
     pub code_selector: SegmentSelector,
@@ -144,6 +191,9 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 
+    let stack_top = unsafe { (*addr_of!(TASK_STATE.tss)).privilege_stack_table[0] };
+    KERNEL_STACK_TOP.store(stack_top.as_u64(), Ordering::Relaxed);
+
     let mut size = 0;
 
     for &s in IST_SIZES.iter() {
@@ -162,3 +212,49 @@ pub fn get_selector() -> &'static KernelSelectors {
 pub fn get_user_selector() -> &'static UserSelectors {
     &GDT.2
 }
+
+/// Ports a user process may access, a clear bit allows the port
+#[derive(Clone)]
+pub struct IoBitmap(Box<[u8]>);
+
+impl IoBitmap {
+    /// A bitmap that denies every port
+    pub fn new() -> Self {
+        Self(vec![0xff; IO_BITMAP_SIZE].into_boxed_slice())
+    }
+
+    /// Allow the ports in `ports`, which must be below `IO_PORTS`
+    pub fn allow(&mut self, ports: Range<u32>) {
+        for port in ports {
+            self.0[port as usize / 8] &= !(1 << (port % 8));
+        }
+    }
+}
+
+impl Default for IoBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Load the ports of the next process into the TSS, `None` denies all of them
+pub fn load_io_bitmap(bitmap: Option<&IoBitmap>) {
+    if bitmap.is_none() && !IO_BITMAP_LOADED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let io_bitmap = unsafe { &mut *addr_of_mut!(TASK_STATE.io_bitmap) };
+    match bitmap {
+        Some(bitmap) => io_bitmap[..IO_BITMAP_SIZE].copy_from_slice(&bitmap.0),
+        None => io_bitmap[..IO_BITMAP_SIZE].fill(0xff),
+    }
+
+    IO_BITMAP_LOADED.store(bitmap.is_some(), Ordering::Relaxed);
+}
+
+/// Set the stack interrupts from user mode switch to, the kernel stack of
+/// the next process
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (*addr_of_mut!(TASK_STATE)).tss.privilege_stack_table[0] = top };
+    KERNEL_STACK_TOP.store(top.as_u64(), Ordering::Relaxed);
+}
@@ -1,9 +1,9 @@
 pub mod address;
 pub mod allocator;
 mod frames;
+pub mod slab;
 
 pub mod gdt;
-pub mod user;
 
 pub use address::*;
 pub use frames::*;
@@ -30,12 +30,9 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     info!("Free Usable Memory : {:>7.*} {}", 3, size, unit);
 
     unsafe {
-        init_FRAME_ALLOCATOR(BootInfoFrameAllocator::init(
-            memory_map,
-            usable_mem_size as usize,
-        ));
+        init_FRAME_ALLOCATOR(BootInfoFrameAllocator::init(memory_map));
     }
-    user::init();
+    allocator::init_heap();
 
     info!("Frame Allocator initialized.");
 }
@@ -0,0 +1,126 @@
+//! Slab caches for small kernel allocations
+//!
+//! Each cache hands out objects of one size class, carved from page sized
+//! slabs taken from the kernel heap. A freed object goes back to the free list
+//! of its cache for the next allocation of the same class, slabs are never
+//! given back to the heap.
+
+use core::alloc::Layout;
+use core::ptr::{NonNull, null_mut};
+
+/// Size of the slabs, objects of a class are aligned to its size inside them
+pub const SLAB_SIZE: usize = 4096;
+
+/// Object sizes of the caches
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Index of the size class serving `layout`, if any
+pub fn size_class(layout: &Layout) -> Option<usize> {
+    SIZE_CLASSES
+        .iter()
+        .position(|&size| layout.size() <= size && layout.align() <= size)
+}
+
+/// Layout of a slab to take from the heap
+pub fn slab_layout() -> Layout {
+    Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+}
+
+/// A free object, linked through its own memory
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Objects of one size class
+///
+/// The size is not kept in the cache, it is passed by the caller.
+pub struct SlabCache {
+    free: *mut FreeObject,
+    /// number of slabs taken from the heap
+    slabs: usize,
+    /// number of objects handed out
+    used: usize,
+    /// number of allocations served since boot
+    allocs: u64,
+}
+
+// the free objects are only reached through the cache
+unsafe impl Send for SlabCache {}
+
+/// Statistics of a slab cache
+#[derive(Clone, Copy, Debug)]
+pub struct SlabUsage {
+    pub size: usize,
+    pub slabs: usize,
+    pub used: usize,
+    pub total: usize,
+    pub allocs: u64,
+}
+
+impl SlabCache {
+    pub const fn new() -> Self {
+        Self {
+            free: null_mut(),
+            slabs: 0,
+            used: 0,
+            allocs: 0,
+        }
+    }
+
+    /// Take an object of `size` bytes, a new slab is taken from `refill`
+    /// when the cache is empty
+    pub fn allocate(
+        &mut self,
+        size: usize,
+        refill: impl FnOnce() -> Option<NonNull<u8>>,
+    ) -> Option<NonNull<u8>> {
+        if self.free.is_null() {
+            let slab = refill()?;
+            self.slabs += 1;
+
+            // link the objects of the new slab, the first one ends up on top
+            for offset in (0..SLAB_SIZE).step_by(size).rev() {
+                unsafe { self.push(slab.as_ptr().add(offset)) };
+            }
+        }
+
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+        self.used += 1;
+        self.allocs += 1;
+
+        NonNull::new(object as *mut u8)
+    }
+
+    /// Give back an object taken from this cache
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `allocate` on this cache and must not be used anymore.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        unsafe { self.push(ptr.as_ptr()) };
+        self.used -= 1;
+    }
+
+    unsafe fn push(&mut self, ptr: *mut u8) {
+        let object = ptr as *mut FreeObject;
+        unsafe { object.write(FreeObject { next: self.free }) };
+        self.free = object;
+    }
+
+    pub fn usage(&self, size: usize) -> SlabUsage {
+        SlabUsage {
+            size,
+            slabs: self.slabs,
+            used: self.used,
+            total: self.slabs * (SLAB_SIZE / size),
+            allocs: self.allocs,
+        }
+    }
+}
+
+impl Default for SlabCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
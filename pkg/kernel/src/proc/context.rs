@@ -1,8 +1,13 @@
 use volatile::{VolatileRef, access::ReadOnly};
 use x86_64::structures::gdt::SegmentSelector;
-use x86_64::{VirtAddr, registers::rflags::RFlags, structures::idt::InterruptStackFrameValue};
+use x86_64::{
+    PrivilegeLevel, VirtAddr, registers::rflags::RFlags, structures::idt::InterruptStackFrameValue,
+};
+
+use syscall_def::{SyscallResult, errno};
 
 use crate::memory::gdt::get_user_selector;
+use crate::proc::uaccess::USER_SPACE_END;
 use crate::{RegistersValue, memory::gdt::get_selector};
 
 #[repr(C)]
@@ -34,6 +39,19 @@ impl ProcessContext {
         self.value.regs.rax = value;
     }
 
+    /// Encode a syscall result into `rax`
+    #[inline]
+    pub fn set_result(&mut self, ret: SyscallResult) {
+        self.set_rax(errno::encode(ret));
+    }
+
+    /// Step back over the `int 0x80` or `syscall` instruction, both are two bytes,
+    /// the syscall is issued again when the process resumes
+    #[inline]
+    pub fn restart_syscall(&mut self) {
+        self.value.stack_frame.instruction_pointer -= 2u64;
+    }
+
     #[inline]
     pub fn save(&mut self, context: &ProcessContext) {
         self.value = context.as_ref().as_ptr().read();
@@ -57,11 +75,11 @@ impl ProcessContext {
         trace!("Init stack frame: {:#?}", &self.stack_frame);
     }
 
+    /// Start in user mode with IOPL 0, I/O ports are only allowed by the TSS bitmap
     pub fn init_user_stack_frame(&mut self, entry: VirtAddr, stack_top: VirtAddr) {
         self.value.stack_frame.stack_pointer = stack_top;
         self.value.stack_frame.instruction_pointer = entry;
-        self.value.stack_frame.cpu_flags =
-            RFlags::IOPL_HIGH | RFlags::IOPL_LOW | RFlags::INTERRUPT_FLAG;
+        self.value.stack_frame.cpu_flags = RFlags::INTERRUPT_FLAG;
 
         let selector = get_user_selector();
         self.value.stack_frame.code_segment = selector.code_selector;
@@ -70,8 +88,75 @@ impl ProcessContext {
         trace!("Init stack frame: {:#?}", &self.stack_frame);
     }
 
-    pub fn set_rsp_offset(&mut self, offset: u64) {
-        self.value.stack_frame.stack_pointer += offset;
+    /// Pass `argc`, `argv` and `envp` to the entry point in `rdi`, `rsi` and `rdx`
+    pub fn set_args(&mut self, argc: usize, argv: VirtAddr, envp: VirtAddr) {
+        self.value.regs.rdi = argc;
+        self.value.regs.rsi = argv.as_u64() as usize;
+        self.value.regs.rdx = envp.as_u64() as usize;
+    }
+
+    /// Whether the context returns to user mode
+    #[inline]
+    pub fn is_user(&self) -> bool {
+        self.value.stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+    }
+
+    /// Whether `sysretq` resumes the context
+    ///
+    /// It returns to the user segments with the instruction pointer and the
+    /// flags taken from `rcx` and `r11`, as the `syscall` instruction left them.
+    /// A context switched to, or rewritten for a signal, goes through `iretq`.
+    pub fn can_sysret(&self) -> bool {
+        let frame = &self.value.stack_frame;
+        let selector = get_user_selector();
+
+        frame.code_segment == selector.code_selector
+            && frame.stack_segment == selector.data_selector
+            && self.value.regs.rcx as u64 == frame.instruction_pointer.as_u64()
+            && self.value.regs.r11 as u64 == frame.cpu_flags.bits()
+            // a non-canonical address would fault in kernel mode
+            && frame.instruction_pointer.as_u64() < USER_SPACE_END
+    }
+
+    #[inline]
+    pub fn value(&self) -> ProcessContextValue {
+        self.value
+    }
+
+    /// Run the signal handler entry at `entry` with `args` in `rdi`, `rsi`, `rdx`
+    pub fn enter_signal_handler(&mut self, entry: usize, stack_top: usize, args: [usize; 3]) {
+        self.value.stack_frame.instruction_pointer = VirtAddr::new(entry as u64);
+        self.value.stack_frame.stack_pointer = VirtAddr::new(stack_top as u64);
+        // the ABI expects the direction flag clear on function entry
+        self.value.stack_frame.cpu_flags -= RFlags::DIRECTION_FLAG;
+
+        self.value.regs.rdi = args[0];
+        self.value.regs.rsi = args[1];
+        self.value.regs.rdx = args[2];
+    }
+
+    /// Restore a context saved in user memory
+    ///
+    /// Only the registers and the arithmetic flags are taken from `value`,
+    /// segments and privileged flags stay as they are.
+    pub fn restore_user(&mut self, value: ProcessContextValue) {
+        let user_flags = RFlags::CARRY_FLAG
+            | RFlags::PARITY_FLAG
+            | RFlags::AUXILIARY_CARRY_FLAG
+            | RFlags::ZERO_FLAG
+            | RFlags::SIGN_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::OVERFLOW_FLAG;
+
+        let trusted = self.value.stack_frame;
+        self.value.regs = value.regs;
+        self.value.stack_frame = InterruptStackFrameValue::new(
+            value.stack_frame.instruction_pointer,
+            trusted.code_segment,
+            (value.stack_frame.cpu_flags & user_flags) | (trusted.cpu_flags - user_flags),
+            value.stack_frame.stack_pointer,
+            trusted.stack_segment,
+        );
     }
 }
 
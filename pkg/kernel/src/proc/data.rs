@@ -1,6 +1,9 @@
-use crate::utils::resource::ResourceSet;
-use alloc::{collections::BTreeMap, sync::Arc};
+use crate::utils::pipe;
+use crate::utils::resource::{Resource, ResourceSet};
+use alloc::{collections::BTreeMap, format, sync::Arc};
 use spin::RwLock;
+use storage::SeekFrom;
+use syscall_def::Errno;
 use x86_64::structures::paging::{
     Page,
     page::{PageRange, PageRangeInclusive},
@@ -31,6 +34,29 @@ impl ProcessData {
         Self::default()
     }
 
+    /// Data of a forked child, with its own copy of the resource table
+    ///
+    /// Open resources, the environment and semaphores stay shared.
+    pub fn fork(&self) -> Self {
+        Self {
+            env: self.env.clone(),
+            resources: Arc::new(RwLock::new(self.resources.read().fork())),
+            semaphores: self.semaphores.clone(),
+        }
+    }
+
+    /// Data of a spawned child, which only inherits the fds
+    /// that are not close-on-exec
+    pub fn spawn(&self) -> Self {
+        let mut resources = self.resources.read().fork();
+        resources.close_on_exec();
+
+        Self {
+            resources: Arc::new(RwLock::new(resources)),
+            ..Self::default()
+        }
+    }
+
     pub fn env(&self, key: &str) -> Option<String> {
         self.env.read().get(key).cloned()
     }
@@ -39,14 +65,86 @@ impl ProcessData {
         self.env.write().insert(key.into(), val.into());
     }
 
-    pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
+    /// The environment as `KEY=VALUE` strings
+    pub fn envs(&self) -> Vec<String> {
+        self.env
+            .read()
+            .iter()
+            .map(|(key, val)| format!("{}={}", key, val))
+            .collect()
+    }
+
+    /// Replace the environment with `KEY=VALUE` strings,
+    /// the new map is no longer shared with forked processes
+    pub fn set_envs(&mut self, envp: &[String]) {
+        let env = envp
+            .iter()
+            .filter_map(|var| var.split_once('='))
+            .map(|(key, val)| (key.into(), val.into()))
+            .collect();
+        self.env = Arc::new(RwLock::new(env));
+    }
+
+    pub fn read(&self, fd: u8, buf: &mut [u8]) -> Result<usize, Errno> {
         self.resources.read().read(fd, buf)
     }
 
-    pub fn write(&self, fd: u8, buf: &[u8]) -> isize {
+    pub fn write(&self, fd: u8, buf: &[u8]) -> Result<usize, Errno> {
         self.resources.read().write(fd, buf)
     }
 
+    pub fn open(&self, res: Resource, cloexec: bool) -> Result<u8, Errno> {
+        self.resources.write().open(res, cloexec)
+    }
+
+    /// Open a pipe, returns the fds of its read and write ends
+    pub fn pipe(&self) -> Result<(u8, u8), Errno> {
+        let (reader, writer) = pipe::pipe();
+        let read_fd = self.open(Resource::PipeReader(reader), false)?;
+        match self.open(Resource::PipeWriter(writer), false) {
+            Ok(write_fd) => Ok((read_fd, write_fd)),
+            Err(errno) => {
+                self.close(read_fd)?;
+                Err(errno)
+            }
+        }
+    }
+
+    pub fn dup(&self, fd: u8, cloexec: bool) -> Result<u8, Errno> {
+        self.resources.write().dup(fd, cloexec)
+    }
+
+    pub fn dup2(&self, old: u8, new: u8, cloexec: bool) -> Result<u8, Errno> {
+        // the replaced resource is dropped after the table is unlocked
+        let replaced = self.resources.write().dup2(old, new, cloexec)?;
+        drop(replaced);
+        Ok(new)
+    }
+
+    /// Close every fd marked close-on-exec
+    pub fn close_on_exec(&self) {
+        let closed = self.resources.write().close_on_exec();
+        drop(closed);
+    }
+
+    pub fn add_waiter(&self, fd: u8, pid: ProcessId) -> Result<(), Errno> {
+        self.resources.read().add_waiter(fd, pid)
+    }
+
+    pub fn close(&self, fd: u8) -> Result<(), Errno> {
+        let res = self.resources.write().close(fd)?;
+        drop(res);
+        Ok(())
+    }
+
+    pub fn seek(&self, fd: u8, pos: SeekFrom) -> Result<usize, Errno> {
+        self.resources.read().seek(fd, pos)
+    }
+
+    pub fn read_at(&self, fd: u8, offset: usize, len: usize) -> Result<Vec<u8>, Errno> {
+        self.resources.read().read_at(fd, offset, len)
+    }
+
     pub fn new_sem(&self, key: u32, val: usize) -> bool {
         self.semaphores.write().insert(key, val)
     }
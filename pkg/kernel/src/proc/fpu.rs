@@ -0,0 +1,157 @@
+//! x87 / SSE / AVX state of the processes
+//!
+//! The kernel is built without SSE, so the registers always hold the state
+//! of the running process. It is saved and restored eagerly with the rest of
+//! the context, with XSAVE when the CPU has it and FXSAVE otherwise.
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86::controlregs::{Xcr0, xcr0_write};
+use x86::cpuid::CpuId;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// Room for the x87, SSE and AVX state in the standard XSAVE format
+const AREA_SIZE: usize = 1024;
+
+/// x87 control word after `fninit`, all exceptions masked
+const INIT_FCW: u16 = 0x037f;
+
+/// MXCSR at reset, all exceptions masked, round to nearest
+const INIT_MXCSR: u32 = 0x1f80;
+
+/// Whether the state is saved with XSAVE
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable SSE for user mode, and XSAVE with AVX if the CPU supports them
+pub fn init() {
+    let cpuid = CpuId::new();
+    let features = cpuid.get_feature_info();
+    let xsave = features.as_ref().is_some_and(|f| f.has_xsave());
+    let avx = features.as_ref().is_some_and(|f| f.has_avx());
+
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+
+    if xsave {
+        let mut xcr0 = Xcr0::XCR0_FPU_MMX_STATE | Xcr0::XCR0_SSE_STATE;
+        if avx {
+            xcr0 |= Xcr0::XCR0_AVX_STATE;
+        }
+
+        unsafe {
+            Cr4::update(|cr4| cr4.insert(Cr4Flags::OSXSAVE));
+            xcr0_write(xcr0);
+        }
+
+        let size = cpuid
+            .get_extended_state_info()
+            .map_or(0, |info| info.xsave_area_size_enabled_features());
+        assert!(size as usize <= AREA_SIZE, "XSAVE area too large: {}", size);
+    }
+
+    XSAVE_ENABLED.store(xsave, Ordering::Relaxed);
+
+    info!(
+        "FPU state saved with {}, AVX {}.",
+        if xsave { "XSAVE" } else { "FXSAVE" },
+        if xsave && avx {
+            "Enabled"
+        } else {
+            "Not Supported"
+        }
+    );
+}
+
+#[derive(Clone)]
+#[repr(C, align(64))]
+struct Area([u8; AREA_SIZE]);
+
+/// Saved extended registers of a process
+#[derive(Clone)]
+pub struct FpuState {
+    area: Box<Area>,
+}
+
+impl FpuState {
+    /// The state after `fninit`, with the SSE and AVX registers cleared
+    ///
+    /// The XSAVE header is left zero, XRSTOR then resets every component
+    /// but MXCSR to its initial value.
+    pub fn new() -> Self {
+        let mut area = Box::new(Area([0; AREA_SIZE]));
+        area.0[0..2].copy_from_slice(&INIT_FCW.to_le_bytes());
+        area.0[24..28].copy_from_slice(&INIT_MXCSR.to_le_bytes());
+        Self { area }
+    }
+
+    /// Save the registers of the running process
+    pub fn save(&mut self) {
+        let area = self.area.0.as_mut_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                xsave(area);
+            } else {
+                fxsave(area);
+            }
+        }
+    }
+
+    /// Load the registers of the next process
+    pub fn restore(&self) {
+        let area = self.area.0.as_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                xrstor(area);
+            } else {
+                fxrstor(area);
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[target_feature(enable = "xsave")]
+unsafe fn xsave(area: *mut u8) {
+    unsafe {
+        core::arch::asm!(
+            "xsave64 [{}]",
+            in(reg) area,
+            in("eax") u32::MAX,
+            in("edx") u32::MAX,
+            options(nostack, preserves_flags),
+        )
+    };
+}
+
+#[target_feature(enable = "xsave")]
+unsafe fn xrstor(area: *const u8) {
+    unsafe {
+        core::arch::asm!(
+            "xrstor64 [{}]",
+            in(reg) area,
+            in("eax") u32::MAX,
+            in("edx") u32::MAX,
+            options(nostack, preserves_flags),
+        )
+    };
+}
+
+#[target_feature(enable = "fxsr")]
+unsafe fn fxsave(area: *mut u8) {
+    unsafe { core::arch::asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags)) };
+}
+
+#[target_feature(enable = "fxsr")]
+unsafe fn fxrstor(area: *const u8) {
+    unsafe { core::arch::asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags)) };
+}
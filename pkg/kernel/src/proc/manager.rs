@@ -1,20 +1,19 @@
 use super::{processor::get_pid, *};
 use crate::humanized_size;
-use crate::memory::user::USER_ALLOCATOR;
-use crate::memory::user::USER_HEAP_SIZE;
-use crate::memory::{
-    self, PAGE_SIZE,
-    allocator::{ALLOCATOR, HEAP_SIZE},
-    get_frame_alloc_for_sure,
-};
+use crate::memory::{self, PAGE_SIZE, allocator::ALLOCATOR, gdt, get_frame_alloc_for_sure};
+use crate::proc::signal::{self, Disposition, SignalFrame};
 use crate::proc::vm::ProcessVm;
+use crate::resource::Resource;
 use alloc::sync::Arc;
 use alloc::sync::Weak;
 use alloc::{collections::*, format};
 use boot::{App, AppListRef};
+use core::ops::Range;
 use spin::{Mutex, RwLock};
+use syscall_def::signal::SigMaskHow;
 use syscall_def::*;
 use uefi::proto::debug;
+use x86_64::structures::paging::{Size4KiB, mapper::MapToError};
 use xmas_elf::ElfFile;
 
 pub static PROCESS_MANAGER: spin::Once<ProcessManager> = spin::Once::new();
@@ -42,6 +41,12 @@ pub struct ProcessManager {
     ready_queue: Mutex<VecDeque<ProcessId>>,
     app_list: AppListRef,
     wait_queue: Mutex<BTreeMap<ProcessId, BTreeSet<ProcessId>>>,
+    /// processes blocked until any of their children exits
+    wait_any: Mutex<BTreeSet<ProcessId>>,
+    /// sleeping processes, sorted by their deadline in nanoseconds
+    sleep_queue: Mutex<BTreeSet<(u64, ProcessId)>>,
+    /// exited children of the kernel process still running on their kernel stack
+    exited_orphans: Mutex<Vec<ProcessId>>,
 }
 
 impl ProcessManager {
@@ -59,6 +64,9 @@ impl ProcessManager {
             ready_queue: Mutex::new(ready_queue),
             app_list,
             wait_queue: Mutex::new(BTreeMap::new()),
+            wait_any: Mutex::new(BTreeSet::new()),
+            sleep_queue: Mutex::new(BTreeSet::new()),
+            exited_orphans: Mutex::new(Vec::new()),
         }
     }
 
@@ -111,6 +119,8 @@ impl ProcessManager {
     // }
 
     pub fn switch_next(&self, context: &mut ProcessContext) -> ProcessId {
+        self.drop_exited_orphans();
+
         // FIXME: fetch the next process from ready queue
         let mut ready_queue = self.ready_queue.lock();
         if ready_queue.is_empty() {
@@ -118,15 +128,22 @@ impl ProcessManager {
             return ProcessId::new();
         }
         let cur_pid = processor::get_pid();
-        let next_pid = ready_queue.pop_front().unwrap();
-        let next_proc = self.get_proc(&next_pid).unwrap();
-        // trace!("Switching to process {:#?}", next_proc);
 
         // FIXME: check if the next process is ready,
         //        continue to fetch if not ready
-        if next_proc.read().status() != ProgramStatus::Ready {
-            return get_pid();
-        }
+        // processes may have been killed or reaped while they were queued
+        let (next_pid, next_proc) = loop {
+            let Some(next_pid) = ready_queue.pop_front() else {
+                return get_pid();
+            };
+            match self.get_proc(&next_pid) {
+                Some(proc) if proc.read().status() == ProgramStatus::Ready => {
+                    break (next_pid, proc);
+                }
+                _ => continue,
+            }
+        };
+        // trace!("Switching to process {:#?}", next_proc);
 
         // FIXME: restore next process's context
         next_proc.write().restore(context);
@@ -170,25 +187,24 @@ impl ProcessManager {
 
     pub fn spawn(
         &self,
-        elf: &ElfFile,
+        elf: &ElfFile<'static>,
         name: String,
         parent: Option<Weak<Process>>,
         proc_data: Option<ProcessData>,
-    ) -> ProcessId {
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<ProcessId, Errno> {
         let kproc = self.get_proc(&KERNEL_PID).unwrap();
-        let page_table = kproc.read().clone_page_table();
-        // let proc_vm = Some(ProcessVm::new(page_table));
-        let proc = Process::new(
-            name,
-            parent,
-            Some(ProcessVm::new(page_table.clone_level_4())),
-            proc_data,
-        );
+        let page_table = kproc.read().clone_page_table().map_err(no_memory)?;
+        // FIXME: load elf to process pagetable
+        let mut proc_vm = ProcessVm::new(page_table);
+        proc_vm.load_elf(elf).map_err(no_memory)?;
+
+        let proc = Process::new(name, parent, Some(proc_vm), proc_data);
         let pid = proc.pid();
+        proc.alloc_kernel_stack().map_err(no_memory)?;
 
         let mut inner = proc.write();
-        // FIXME: load elf to process pagetable
-        inner.load_elf(elf);
         // FIXME: alloc new stack for process
         let stack_top: VirtAddr = inner.vm_mut().init_user_proc_stack(pid);
         info!(
@@ -200,6 +216,8 @@ impl ProcessManager {
         inner.init_user_stack_frame(
             VirtAddr::new(elf.header.pt2.entry_point() as u64),
             stack_top,
+            argv,
+            envp,
         );
         // FIXME: mark process as ready
         inner.pause();
@@ -208,20 +226,62 @@ impl ProcessManager {
         trace!("New {:#?}", &proc);
 
         let pid = proc.pid();
+        if let Some(parent) = proc.read().parent() {
+            parent.write().add_child(Arc::clone(&proc));
+        }
+
         // FIXME: something like kernel thread
         self.add_proc(pid, Arc::clone(&proc));
         self.push_ready(pid);
 
-        pid
+        Ok(pid)
+    }
+
+    /// Replace the image of the current process with `elf`
+    /// and load the new context into `context`
+    pub fn exec(
+        &self,
+        elf: &ElfFile<'static>,
+        name: String,
+        argv: &[String],
+        envp: &[String],
+        context: &mut ProcessContext,
+    ) -> Result<(), Errno> {
+        let kproc = self.get_proc(&KERNEL_PID).unwrap();
+        let page_table = kproc.read().clone_page_table().map_err(no_memory)?;
+        let mut proc_vm = ProcessVm::new(page_table);
+
+        let proc = self.current();
+        let pid = proc.pid();
+
+        proc_vm.load_elf(elf).map_err(no_memory)?;
+        let stack_top = proc_vm.init_user_proc_stack(pid);
+        let entry = VirtAddr::new(elf.header.pt2.entry_point());
+
+        info!(
+            "exec: pid={} entry={:#x} stack_top={:#x}",
+            pid, entry, stack_top
+        );
+
+        let mut inner = proc.write();
+        inner.exec(name, proc_vm, entry, stack_top, argv, envp);
+        inner.restore(context);
+        let proc_data = ProcessData::clone(&inner);
+        drop(inner);
+
+        proc_data.close_on_exec();
+        Ok(())
     }
 
-    pub fn fork(&self) -> u64 {
+    pub fn fork(&self) -> Result<u64, Errno> {
         // FIXME: get current process
         let parent = self.current();
         let parent_pid = parent.pid();
         trace!("Forking process: {}#{}", parent.read().name(), parent_pid);
         // FIXME: fork to get child
-        let child = parent.fork();
+        let kproc = self.get_proc(&KERNEL_PID).unwrap();
+        let page_table = kproc.read().clone_page_table().map_err(no_memory)?;
+        let child = parent.fork(page_table).map_err(no_memory)?;
         let child_pid = child.pid();
         trace!(
             "Forked child process: {}#{}",
@@ -240,7 +300,7 @@ impl ProcessManager {
         // FOR DBG: maybe print the process ready queue?
         trace!("Queue  : {:?}\n", self.ready_queue.lock());
 
-        child_pid.0 as u64
+        Ok(child_pid.0 as u64)
     }
 
     pub fn block(&self, pid: ProcessId) {
@@ -250,20 +310,88 @@ impl ProcessManager {
         }
     }
 
-    pub fn wait_pid(&self, pid: ProcessId) {
-        let mut wait_queue = self.wait_queue.lock();
+    /// Block the current process until `pid` exits, or any child if `None`
+    pub fn wait_pid(&self, pid: Option<ProcessId>) {
         // FIXME: push the current process to the wait queue
         //        `processor::get_pid()` is waiting for `pid`
         let cur_pid = processor::get_pid();
-        wait_queue
-            .entry(pid)
-            .or_insert_with(BTreeSet::new)
-            .insert(cur_pid);
+        match pid {
+            Some(pid) => {
+                self.wait_queue
+                    .lock()
+                    .entry(pid)
+                    .or_insert_with(BTreeSet::new)
+                    .insert(cur_pid);
+            }
+            None => {
+                self.wait_any.lock().insert(cur_pid);
+            }
+        }
+    }
+
+    /// Reap an exited child of the current process
+    ///
+    /// `pid` selects a child, `None` accepts any of them. Returns the pid and
+    /// exit code of the reaped child, or `None` if they are all still running.
+    pub fn try_wait(&self, pid: Option<ProcessId>) -> Result<Option<(ProcessId, isize)>, Errno> {
+        let proc = self.current();
+        let mut inner = proc.write();
+
+        let mut matched = inner
+            .children()
+            .iter()
+            .filter(|c| pid.is_none_or(|pid| c.pid() == pid))
+            .peekable();
+
+        if matched.peek().is_none() {
+            return Err(Errno::NoChild);
+        }
+
+        let exited = matched.find_map(|c| c.read().exit_code().map(|code| (c.pid(), code)));
+
+        if let Some((child_pid, _)) = exited {
+            inner.remove_child(child_pid);
+            self.processes.write().remove(&child_pid);
+            trace!("Reaped process #{}", child_pid);
+        }
+
+        Ok(exited)
+    }
+
+    /// Put the current process to sleep until the monotonic clock reaches `deadline`
+    pub fn sleep(&self, deadline: u64) {
+        let cur_pid = processor::get_pid();
+        self.sleep_queue.lock().insert((deadline, cur_pid));
+    }
+
+    /// Wake up every sleeping process whose deadline has passed
+    pub fn wake_up_sleepers(&self, now: u64) {
+        loop {
+            let mut sleep_queue = self.sleep_queue.lock();
+            let (deadline, pid) = match sleep_queue.first() {
+                Some(&(deadline, pid)) if deadline <= now => (deadline, pid),
+                _ => break,
+            };
+            sleep_queue.remove(&(deadline, pid));
+            drop(sleep_queue);
+
+            // the process may have been killed while sleeping
+            let blocked = self
+                .get_proc(&pid)
+                .is_some_and(|proc| proc.read().status() == ProgramStatus::Blocked);
+            if blocked {
+                self.wake_up(pid, Some(0));
+            }
+        }
     }
 
     pub fn wake_up(&self, pid: ProcessId, ret: Option<isize>) {
         if let Some(proc) = self.get_proc(&pid) {
             let mut inner = proc.write();
+            // a killed process stays dead until it is reaped
+            if inner.status() == ProgramStatus::Dead {
+                return;
+            }
             if let Some(ret) = ret {
                 // FIXME: set the return value of the process
                 //        like `context.set_rax(ret as usize)`
@@ -282,9 +410,21 @@ impl ProcessManager {
         self.kill(processor::get_pid(), ret);
     }
 
-    pub fn handle_page_fault(&self, addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
+    pub fn handle_page_fault(
+        &self,
+        addr: VirtAddr,
+        err_code: PageFaultErrorCode,
+        context: &mut ProcessContext,
+    ) -> bool {
         // FIXME: handle page fault
         if err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            // a write to a page shared with a forked process
+            if err_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                && self.handle_with_oom_kill(|inner| inner.handle_cow_fault(addr), context)
+            {
+                return true;
+            }
+
             warn!("Page fault: protection violation at {:#x}", addr.as_u64());
             return false;
         }
@@ -297,11 +437,6 @@ impl ProcessManager {
             return false;
         }
 
-        if err_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
-            error!("Page fault: instruction fetch at {:#x}", addr.as_u64());
-            return false;
-        }
-
         if err_code.contains(PageFaultErrorCode::PROTECTION_KEY) {
             error!(
                 "Page fault: protection key violation at {:#x}",
@@ -332,7 +467,79 @@ impl ProcessManager {
             info!("Kernel page fault at {:#x}", addr.as_u64());
         }
 
-        self.current().write().handle_page_fault(addr)
+        self.handle_with_oom_kill(|inner| inner.handle_page_fault(addr), context)
+    }
+
+    /// Run `handle` on the current process, as long as it runs out of frames
+    /// the largest user process is killed and `handle` runs again
+    ///
+    /// A victim blocked in a syscall only frees its memory once it runs and
+    /// leaves the kernel. Until then a fault from user mode switches to the
+    /// next process in `context` and is retried when the faulting process
+    /// runs again, instead of killing another process. A fault in the kernel
+    /// cannot wait and goes on with the next victim.
+    ///
+    /// Gives up once the current process is the one killed.
+    fn handle_with_oom_kill(
+        &self,
+        handle: impl Fn(&mut ProcessInner) -> Result<bool, MapToError<Size4KiB>>,
+        context: &mut ProcessContext,
+    ) -> bool {
+        let proc = self.current();
+
+        loop {
+            let result = handle(&mut proc.write());
+            match result {
+                Ok(handled) => return handled,
+                Err(err) => warn!("Process #{} is out of memory: {:?}", proc.pid(), err),
+            }
+
+            if context.is_user() && self.has_pending_exit() {
+                // the faulting instruction runs again once switched back
+                self.push_ready(proc.pid());
+                self.save_current(context);
+                self.switch_next(context);
+                return true;
+            }
+
+            match self.kill_largest() {
+                Some(pid) if pid != proc.pid() => {}
+                _ => return false,
+            }
+        }
+    }
+
+    /// Whether a killed process has yet to leave the kernel and exit
+    fn has_pending_exit(&self) -> bool {
+        self.processes.read().values().any(|p| {
+            let inner = p.read();
+            inner.status() != ProgramStatus::Dead && inner.exit_pending()
+        })
+    }
+
+    /// Kill the user process with the most resident memory, returns its pid
+    fn kill_largest(&self) -> Option<ProcessId> {
+        let (pid, resident) = self
+            .processes
+            .read()
+            .values()
+            .filter(|p| p.pid() != KERNEL_PID)
+            .filter_map(|p| {
+                let inner = p.read();
+                // a process with a pending exit frees its memory soon
+                (inner.status() != ProgramStatus::Dead && !inner.exit_pending())
+                    .then(|| (p.pid(), inner.vm().memory_usage().resident))
+            })
+            .max_by_key(|&(_, resident)| resident)?;
+
+        let (size, unit) = humanized_size(resident);
+        println!(
+            "[!] Out of memory, killing process #{} ({:.3} {} resident)",
+            pid, size, unit
+        );
+        self.kill(pid, Signal::Kill.exit_code());
+
+        Some(pid)
     }
 
     pub fn kill(&self, pid: ProcessId, ret: isize) {
@@ -350,19 +557,303 @@ impl ProcessManager {
             return;
         }
 
+        // a process blocked in a syscall still has frames on its kernel stack,
+        // it is woken up to leave the syscall and exits on its way out
+        let mut inner = proc.write();
+        if inner.is_in_kernel() {
+            inner.set_pending_exit(ret);
+            let blocked = inner.status() == ProgramStatus::Blocked;
+            drop(inner);
+            if blocked {
+                self.wake_up(pid, None);
+            }
+            return;
+        }
+        drop(inner);
+
         trace!("Kill {:#?}", &proc);
 
         proc.kill(ret);
 
+        // nobody is left to wait for the children, drop the exited ones
+        // and hand the running ones over to the kernel process
+        let kproc = self.get_proc(&KERNEL_PID).unwrap();
+        let children = proc.write().take_children();
+        for child in children {
+            if child.read().status() == ProgramStatus::Dead {
+                self.processes.write().remove(&child.pid());
+            } else {
+                child.write().set_parent(Arc::downgrade(&kproc));
+                kproc.write().add_child(child);
+            }
+        }
+
+        // waiters restart `wait_pid` and collect the exit code themselves
         if let Some(pids) = self.wait_queue.lock().remove(&pid) {
             for pid in pids {
-                self.wake_up(pid, Some(ret));
+                self.wake_up(pid, None);
+            }
+        }
+
+        let parent = proc.read().parent();
+        if let Some(parent) = parent {
+            if parent.pid() == KERNEL_PID {
+                // the kernel process never waits for its children
+                self.reap_orphan(&parent, pid);
+                return;
+            }
+            if self.wait_any.lock().remove(&parent.pid()) {
+                self.wake_up(parent.pid(), None);
+            }
+            let _ = self.send_signal(parent.pid(), Some(Signal::Chld));
+        }
+    }
+
+    /// Drop the exited child `pid` of the kernel process `kproc`
+    ///
+    /// The current process still runs on its kernel stack, it is dropped
+    /// once another process has been switched to.
+    fn reap_orphan(&self, kproc: &Process, pid: ProcessId) {
+        kproc.write().remove_child(pid);
+        if pid == processor::get_pid() {
+            self.exited_orphans.lock().push(pid);
+        } else {
+            self.processes.write().remove(&pid);
+            trace!("Reaped orphan process #{}", pid);
+        }
+    }
+
+    /// Drop the orphans left by `reap_orphan`, except the current process
+    fn drop_exited_orphans(&self) {
+        let cur_pid = processor::get_pid();
+        self.exited_orphans.lock().retain(|&pid| {
+            if pid == cur_pid {
+                return true;
+            }
+            self.processes.write().remove(&pid);
+            trace!("Reaped orphan process #{}", pid);
+            false
+        });
+    }
+
+    /// Allow `pid` to access `ports` from user mode
+    ///
+    /// Only the kernel and the drivers it spawned may grant ports.
+    pub fn grant_io_ports(&self, pid: ProcessId, ports: Range<u32>) -> Result<(), Errno> {
+        let current = self.current();
+        let privileged = current.pid() == KERNEL_PID || current.read().is_io_privileged();
+        if !privileged || pid == KERNEL_PID {
+            return Err(Errno::NotPermitted);
+        }
+
+        let proc = self.get_proc(&pid).ok_or(Errno::NoProcess)?;
+        let mut inner = proc.write();
+        if inner.status() == ProgramStatus::Dead {
+            return Err(Errno::NoProcess);
+        }
+
+        inner.grant_io_ports(ports.clone());
+        if pid == current.pid() {
+            gdt::load_io_bitmap(inner.io_bitmap());
+        }
+
+        info!(
+            "Process #{} granted I/O ports {:#x}-{:#x} to process #{}",
+            current.pid(),
+            ports.start,
+            ports.end - 1,
+            pid
+        );
+        Ok(())
+    }
+
+    /// Send `sig` to `pid`, `None` only checks that `pid` can be signaled
+    ///
+    /// Signals that terminate the target kill it right away, even if it is
+    /// blocked. Caught signals stay pending until the target returns to
+    /// user mode, and interrupt it if it sleeps or waits for a child.
+    pub fn send_signal(&self, pid: ProcessId, sig: Option<Signal>) -> Result<(), Errno> {
+        if pid == KERNEL_PID {
+            return Err(Errno::NotPermitted);
+        }
+
+        let proc = self.get_proc(&pid).ok_or(Errno::NoProcess)?;
+        let mut inner = proc.write();
+        if inner.status() == ProgramStatus::Dead {
+            return Err(Errno::NoProcess);
+        }
+
+        let Some(sig) = sig else {
+            return Ok(());
+        };
+
+        let signals = inner.signals_mut();
+        if signals.is_blocked(sig) {
+            signals.raise(sig);
+            return Ok(());
+        }
+
+        match signals.disposition(sig) {
+            Disposition::Ignore => {}
+            Disposition::Terminate => {
+                drop(inner);
+                debug!("Process #{} terminated by {:?}", pid, sig);
+                self.kill(pid, sig.exit_code());
+            }
+            Disposition::Catch(_) => {
+                signals.raise(sig);
+                let blocked = inner.status() == ProgramStatus::Blocked;
+                drop(inner);
+                if blocked {
+                    self.interrupt(pid);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send the signal of `fault` to the current process, then act on it
+    ///
+    /// The faulting instruction runs again if a handler returns, so unless
+    /// the signal is caught the process is terminated with a crash report
+    /// and the exit code of the signal. Its waiters are woken up by `kill`.
+    pub fn raise_fault(&self, fault: &UserFault, context: &mut ProcessContext) {
+        let proc = self.current();
+        let pid = proc.pid();
+
+        // killed to free memory while handling the fault
+        if proc.read().status() == ProgramStatus::Dead {
+            self.handle_signals(context);
+            return;
+        }
+
+        let mut inner = proc.write();
+        let signals = inner.signals_mut();
+        let caught = !signals.is_blocked(fault.sig)
+            && matches!(signals.disposition(fault.sig), Disposition::Catch(_));
+
+        if caught {
+            signals.raise(fault.sig);
+            drop(inner);
+        } else {
+            fault.report(pid, inner.name(), context);
+            drop(inner);
+            self.kill(pid, fault.sig.exit_code());
+        }
+
+        self.handle_signals(context);
+    }
+
+    /// Wake `pid` early from an interruptible block to run a signal handler
+    ///
+    /// `sleep` returns `Interrupted`, `wait_pid` is issued again after the
+    /// handler returns. Semaphore waits are not interrupted.
+    fn interrupt(&self, pid: ProcessId) {
+        let mut sleep_queue = self.sleep_queue.lock();
+        let len = sleep_queue.len();
+        sleep_queue.retain(|&(_, p)| p != pid);
+        let slept = sleep_queue.len() != len;
+        drop(sleep_queue);
+
+        if slept {
+            self.wake_up(pid, Some(Errno::Interrupted.as_ret() as isize));
+            return;
+        }
+
+        let mut waited = self.wait_any.lock().remove(&pid);
+        for waiters in self.wait_queue.lock().values_mut() {
+            waited |= waiters.remove(&pid);
+        }
+
+        if waited {
+            self.wake_up(pid, None);
+        }
+    }
+
+    /// Act on the pending signals of the current process before it
+    /// returns to user mode
+    ///
+    /// Runs the default action, or rewrites `context` to enter the handler.
+    /// If the current process is dead by then, switch to the next one.
+    pub fn handle_signals(&self, context: &mut ProcessContext) {
+        loop {
+            let proc = self.current();
+            let pid = proc.pid();
+
+            if proc.read().status() == ProgramStatus::Dead {
+                self.switch_next(context);
+                if processor::get_pid() == pid {
+                    return;
+                }
+                continue;
+            }
+
+            if !context.is_user() {
+                return;
+            }
+
+            // killed while blocked in the syscall it returns from
+            let pending_exit = proc.write().take_pending_exit();
+            if let Some(ret) = pending_exit {
+                debug!("Process #{} left the kernel, exiting", pid);
+                self.kill(pid, ret);
+                continue;
+            }
+
+            let mut inner = proc.write();
+            let signals = inner.signals_mut();
+            let Some(sig) = signals.take_pending() else {
+                return;
+            };
+
+            match signals.disposition(sig) {
+                Disposition::Ignore => {}
+                Disposition::Terminate => {
+                    drop(inner);
+                    debug!("Process #{} terminated by {:?}", pid, sig);
+                    self.kill(pid, sig.exit_code());
+                }
+                Disposition::Catch(handler) => {
+                    let frame = SignalFrame {
+                        context: context.value(),
+                        blocked: signals.blocked().bits(),
+                    };
+                    let trampoline = signals.trampoline();
+                    // the signal stays blocked until the handler returns
+                    signals.set_mask(SigMaskHow::Block, sig.into());
+                    drop(inner);
+
+                    if signal::enter_handler(context, &frame, sig, handler, trampoline) {
+                        return;
+                    }
+
+                    warn!("Process #{} cannot take a signal frame", pid);
+                    self.kill(pid, Signal::Segv.exit_code());
+                }
             }
         }
     }
 
+    /// Return from a signal handler, restoring the context saved at `frame_addr`
+    pub fn sigreturn(&self, frame_addr: usize, context: &mut ProcessContext) {
+        let Some(frame) = signal::read_frame(frame_addr) else {
+            warn!("Process #{} has a bad signal frame", get_pid());
+            self.kill_current(Signal::Segv.exit_code());
+            return;
+        };
+
+        context.restore_user(frame.context);
+        self.current()
+            .write()
+            .signals_mut()
+            .set_mask(SigMaskHow::SetMask, SigSet::from_bits(frame.blocked));
+    }
+
     pub fn print_process_list(&self) {
-        let mut output = String::from("  PID | PPID | Process Name |  Ticks  | Status\n");
+        let mut output = String::from(
+            "  PID | PPID | Process Name |  Ticks  |  Resident / Reserved  | Status\n",
+        );
 
         self.processes
             .read()
@@ -370,7 +861,6 @@ impl ProcessManager {
             .filter(|p| p.read().status() != ProgramStatus::Dead)
             .for_each(|p| output += format!("{}\n", p).as_str());
 
-        // TODO: print memory usage of kernel heap
         let alloc = get_frame_alloc_for_sure();
         let frames_used = alloc.frames_used();
         let frames_total = alloc.frames_total();
@@ -394,11 +884,28 @@ impl ProcessManager {
         )
         .as_str();
 
-        // output += &Self::format_usage(
-        //     "Memory",
-        //     used as usize,
-        //     total as usize,
-        // );
+        for usage in alloc.usage() {
+            output += &Self::format_usage(
+                &format!("{:?}", usage.ty),
+                usage.used * PAGE_SIZE as usize,
+                usage.total * PAGE_SIZE as usize,
+            );
+        }
+
+        // Print Memory Usage of kernel heap
+        let heap = ALLOCATOR.heap_usage();
+        output += &Self::format_usage("Heap", heap.used, heap.size);
+        let bootstrap = ALLOCATOR.bootstrap_usage();
+        output += &Self::format_usage("Boot", bootstrap.used, bootstrap.size);
+
+        output += "\nSlab Caches:\n";
+        for cache in ALLOCATOR.slab_usage() {
+            output += format!(
+                "{:>5}B : {:>6} / {:>6} objects in {:>4} slabs, {} allocs\n",
+                cache.size, cache.used, cache.total, cache.slabs, cache.allocs
+            )
+            .as_str();
+        }
 
         // Print Memory Usage of each process
         output += "\nProcess Memory Usage:\n";
@@ -448,11 +955,45 @@ impl ProcessManager {
         self.app_list
     }
 
-    pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
+    pub fn read(&self, fd: u8, buf: &mut [u8]) -> Result<usize, Errno> {
         self.current().write().read(fd, buf)
     }
 
-    pub fn write(&self, fd: u8, buf: &[u8]) -> isize {
+    pub fn write(&self, fd: u8, buf: &[u8]) -> Result<usize, Errno> {
         self.current().write().write(fd, buf)
     }
+
+    pub fn open(&self, res: Resource, cloexec: bool) -> Result<u8, Errno> {
+        self.current().read().open(res, cloexec)
+    }
+
+    pub fn close(&self, fd: u8) -> Result<(), Errno> {
+        // closing a pipe wakes up other processes, do it without the lock
+        let proc_data = ProcessData::clone(&self.current().read());
+        proc_data.close(fd)
+    }
+
+    pub fn pipe(&self) -> Result<(u8, u8), Errno> {
+        self.current().read().pipe()
+    }
+
+    pub fn dup(&self, fd: u8, cloexec: bool) -> Result<u8, Errno> {
+        self.current().read().dup(fd, cloexec)
+    }
+
+    pub fn dup2(&self, old: u8, new: u8, cloexec: bool) -> Result<u8, Errno> {
+        // like `close`, the replaced resource is dropped without the lock
+        let proc_data = ProcessData::clone(&self.current().read());
+        proc_data.dup2(old, new, cloexec)
+    }
+
+    pub fn seek(&self, fd: u8, pos: SeekFrom) -> Result<usize, Errno> {
+        self.current().read().seek(fd, pos)
+    }
+}
+
+/// Report a failed allocation of frames for a process to user space
+fn no_memory(err: MapToError<Size4KiB>) -> Errno {
+    warn!("Out of memory: {:?}", err);
+    Errno::NoMemory
 }
@@ -1,17 +1,21 @@
 mod context;
 mod data;
+pub mod fpu;
 pub mod manager;
 mod paging;
 mod pid;
 mod process;
 pub mod processor;
+mod signal;
 mod sync;
+pub mod uaccess;
 
-use crate::memory::PAGE_SIZE;
+use crate::memory::{PAGE_SIZE, gdt};
 use manager::*;
 use process::*;
 
 use crate::alloc::string::ToString;
+use alloc::format;
 use alloc::string::String;
 pub use context::ProcessContext;
 pub use data::ProcessData;
@@ -24,12 +28,21 @@ use crate::drivers::filesystem::*;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use boot::BootInfo;
+use core::result::Result;
 use storage::*;
+use syscall_def::signal::{SIG_DFL, SIG_IGN, SigMaskHow};
+use syscall_def::{
+    ARG_MAX, Errno, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MmapArgs, O_APPEND, O_CLOEXEC, O_CREAT,
+    O_TRUNC, O_WRONLY, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE, SigSet, Signal, WNOHANG,
+};
 use xmas_elf::ElfFile;
 
 use crate::proc::vm::ProcessVm;
+use crate::resource::Resource;
+use crate::utils::clock;
 use x86_64::VirtAddr;
 use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::PageTableFlags;
 pub const KERNEL_PID: ProcessId = ProcessId(1);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -103,9 +116,55 @@ pub fn process_exit(ret: isize) -> ! {
     }
 }
 
-pub fn handle_page_fault(addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
+/// Handle a page fault of the current process, it may be switched out in `context`
+pub fn handle_page_fault(
+    addr: VirtAddr,
+    err_code: PageFaultErrorCode,
+    context: &mut ProcessContext,
+) -> bool {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        get_process_manager().handle_page_fault(addr, err_code)
+        get_process_manager().handle_page_fault(addr, err_code, context)
+    })
+}
+
+/// An exception caused by the current process in user mode
+pub struct UserFault {
+    /// name of the exception
+    pub exception: &'static str,
+    /// signal reporting the exception to the process
+    pub sig: Signal,
+    /// address the process tried to access, for page faults
+    pub addr: Option<u64>,
+    pub err_code: Option<u64>,
+}
+
+impl UserFault {
+    /// Print the crash report of process `pid` terminated by the fault
+    pub fn report(&self, pid: ProcessId, name: &str, context: &ProcessContext) {
+        let hex = |value: Option<u64>| value.map_or(String::from("-"), |v| format!("{:#x}", v));
+
+        println!(
+            "[!] Process #{} ({}) crashed: {}",
+            pid, name, self.exception
+        );
+        println!(
+            "    rip: {:#x}, address: {}, error code: {}",
+            context.value().stack_frame.instruction_pointer.as_u64(),
+            hex(self.addr),
+            hex(self.err_code)
+        );
+        println!(
+            "    terminated by {:?}, exit code {}",
+            self.sig,
+            self.sig.exit_code()
+        );
+    }
+}
+
+/// Report a fault of the current process in user mode
+pub fn raise_fault(fault: &UserFault, context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().raise_fault(fault, context);
     })
 }
 
@@ -130,16 +189,111 @@ pub fn list_app() {
     });
 }
 
-pub fn spawn(name: &str) -> Option<ProcessId> {
+/// Spawn the app `name` as a child of the current process
+///
+/// An empty `argv` passes the name as the only argument,
+/// `envp` defaults to the environment of the current process.
+pub fn spawn(name: &str, argv: Vec<String>, envp: Option<Vec<String>>) -> Result<ProcessId, Errno> {
     let app = x86_64::instructions::interrupts::without_interrupts(|| {
-        let app_list = get_process_manager()
+        get_process_manager()
             .app_list()
-            .expect("App list not found");
-        app_list.iter().find(|&app| app.name.eq(name))
+            .and_then(|app_list| app_list.iter().find(|&app| app.name.eq(name)))
     })
-    .expect("App not found");
+    .ok_or(Errno::NotFound)?;
+
+    check_elf(&app.elf)?;
+    let (argv, envp) = prepare_args(name, argv, envp)?;
+    elf_spawn(name.to_string(), &app.elf, &argv, &envp)
+}
+
+/// Spawn the app `name` as a driver that may grant I/O ports
+///
+/// Only the kernel spawns drivers, from the boot-time list in `main.rs`,
+/// see `grant_io_ports`.
+pub fn spawn_driver(name: &str, argv: Vec<String>) -> Result<ProcessId, Errno> {
+    // mark the driver before it gets the chance to run
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let pid = spawn(name, argv, None)?;
+        let proc = get_process_manager()
+            .get_proc_public(&pid)
+            .ok_or(Errno::NoProcess)?;
+        proc.write().set_io_privileged();
+        Ok(pid)
+    })
+}
+
+/// Replace the current process image with the app `name`
+///
+/// only returns if the app cannot be found or loaded,
+/// `argv` and `envp` default like in `spawn`
+pub fn exec(
+    name: &str,
+    argv: Vec<String>,
+    envp: Option<Vec<String>>,
+    context: &mut ProcessContext,
+) -> Result<(), Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        let app = manager
+            .app_list()
+            .and_then(|app_list| app_list.iter().find(|&app| app.name.eq(name)))
+            .ok_or(Errno::NotFound)?;
+
+        check_elf(&app.elf)?;
+        let (argv, envp) = prepare_args(name, argv, envp)?;
+
+        manager.exec(&app.elf, name.to_string(), &argv, &envp, context)
+    })
+}
 
-    elf_spawn(name.to_string(), &app.elf)
+/// Fill in the default arguments and check that they fit on the new stack
+fn prepare_args(
+    name: &str,
+    mut argv: Vec<String>,
+    envp: Option<Vec<String>>,
+) -> Result<(Vec<String>, Vec<String>), Errno> {
+    if argv.is_empty() {
+        argv.push(name.to_string());
+    }
+
+    let envp = envp.unwrap_or_else(|| {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            get_process_manager().current().read().envs()
+        })
+    });
+
+    // strings with their terminators, plus argc and both null-terminated arrays
+    let size = argv
+        .iter()
+        .chain(&envp)
+        .map(|s| s.len() + 1 + size_of::<usize>())
+        .sum::<usize>()
+        + 3 * size_of::<usize>();
+
+    if size > ARG_MAX {
+        return Err(Errno::ArgListTooLong);
+    }
+
+    Ok((argv, envp))
+}
+
+/// Reject ELF files that cannot run as a user process
+fn check_elf(elf: &ElfFile) -> Result<(), Errno> {
+    use xmas_elf::header::{Machine, Type};
+
+    let header = &elf.header.pt2;
+    if header.type_().as_type() != Type::Executable
+        || header.machine().as_machine() != Machine::X86_64
+    {
+        return Err(Errno::NoExec);
+    }
+
+    // segments are loaded from the image when they are accessed
+    if !vm::segment::check_segments(elf) {
+        return Err(Errno::NoExec);
+    }
+
+    Ok(())
 }
 
 // pub fn spawn(path: &str) -> Option<ProcessId> {
@@ -160,28 +314,138 @@ pub fn spawn(name: &str) -> Option<ProcessId> {
 //     elf_spawn(exec_name, &elf)
 // }
 
-pub fn elf_spawn(name: String, elf: &ElfFile) -> Option<ProcessId> {
+pub fn elf_spawn(
+    name: String,
+    elf: &ElfFile<'static>,
+    argv: &[String],
+    envp: &[String],
+) -> Result<ProcessId, Errno> {
     let pid = x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
         let process_name = name.to_lowercase();
         let parent = Arc::downgrade(&manager.current());
-        let pid = manager.spawn(elf, name, Some(parent), None);
+        let mut proc_data = manager.current().read().spawn();
+        proc_data.set_envs(envp);
+        let pid = manager.spawn(elf, name, Some(parent), Some(proc_data), argv, envp)?;
 
         debug!("Spawned process: {}#{}", process_name, pid);
-        pid
-    });
+        Ok(pid)
+    })?;
 
-    Some(pid)
+    Ok(pid)
 }
 
-pub fn read(fd: u8, buf: &mut [u8]) -> isize {
+pub fn read(fd: u8, buf: &mut [u8]) -> Result<usize, Errno> {
     x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().read(fd, buf))
 }
 
-pub fn write(fd: u8, buf: &[u8]) -> isize {
+pub fn write(fd: u8, buf: &[u8]) -> Result<usize, Errno> {
     x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().write(fd, buf))
 }
 
+/// Open the file at `path`, `flags` is a combination of the `O_*` flags
+pub fn open(path: &str, flags: usize) -> Result<u8, Errno> {
+    if flags & !(O_WRONLY | O_CREAT | O_TRUNC | O_APPEND | O_CLOEXEC) != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let fs = get_rootfs();
+        let exists = || fs.exists(path).unwrap_or(false);
+
+        let file = if flags & O_TRUNC != 0 || (flags & O_CREAT != 0 && !exists()) {
+            fs.create_file(path)
+        } else if flags & O_APPEND != 0 {
+            fs.append_file(path)
+        } else if flags & O_WRONLY != 0 {
+            fs.open_file_for_write(path)
+        } else {
+            fs.open_file(path)
+        }
+        .map_err(fs_errno)?;
+
+        get_process_manager().open(Resource::File(file), flags & O_CLOEXEC != 0)
+    })
+}
+
+pub fn pipe() -> Result<(u8, u8), Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().pipe())
+}
+
+pub fn dup(fd: u8, cloexec: bool) -> Result<u8, Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().dup(fd, cloexec))
+}
+
+pub fn dup2(old: u8, new: u8, cloexec: bool) -> Result<u8, Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().dup2(old, new, cloexec)
+    })
+}
+
+/// Block in place until a read or write on `fd` that would block can make progress
+pub fn wait_resource(fd: u8) -> Result<(), Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let proc = get_process_manager().current();
+        proc.read().add_waiter(fd, proc.pid())?;
+        drop(proc);
+        block_in_place()
+    })
+}
+
+/// Block the current process in the middle of a syscall until it is woken up
+///
+/// The syscall goes on where it left off, on the kernel stack of the process.
+/// The caller registers the process as a waiter first, e.g. on a pipe.
+/// Returns `Interrupted` if the process has been killed meanwhile, it exits
+/// when it leaves the syscall.
+pub fn block_in_place() -> Result<(), Errno> {
+    crate::interrupt::syscall::yield_cpu();
+
+    let proc = get_process_manager().current();
+    if proc.read().exit_pending() {
+        return Err(Errno::Interrupted);
+    }
+    Ok(())
+}
+
+/// Save the kernel context of the current process, which blocks in place,
+/// and switch to the next one
+pub fn block_current(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        let pid = processor::get_pid();
+        manager.save_current(context);
+        manager.current().write().block_in_kernel();
+        manager.switch_next(context);
+
+        // nothing else to run, go on with the syscall
+        if processor::get_pid() == pid {
+            manager.current().write().restore(context);
+        }
+    })
+}
+
+/// Block the current process and issue the syscall again once it is woken up
+///
+/// The caller registers the process as a waiter first, e.g. in the wait queue of a child.
+pub fn block_and_restart(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        context.restart_syscall();
+        manager.save_current(context);
+        manager.current().write().block();
+        manager.switch_next(context);
+    })
+}
+
+pub fn close(fd: u8) -> Result<(), Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().close(fd))
+}
+
+pub fn seek(fd: u8, pos: SeekFrom) -> Result<usize, Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().seek(fd, pos))
+}
+
 pub fn exit(ret: isize, context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
@@ -191,56 +455,174 @@ pub fn exit(ret: isize, context: &mut ProcessContext) {
     })
 }
 
-pub fn wait_pid(pid: u16, context: &mut ProcessContext) {
+pub fn wait_pid(
+    pid: Option<ProcessId>,
+    status: usize,
+    options: usize,
+    context: &mut ProcessContext,
+) {
     x86_64::instructions::interrupts::without_interrupts(|| {
+        // make sure the status can be stored before reaping the child
+        if status != 0 && !uaccess::check_user_range(status, size_of::<isize>(), true) {
+            context.set_result(Err(Errno::Fault));
+            return;
+        }
+
         let manager = get_process_manager();
-        let pid = ProcessId(pid);
-        if let Some(ret) = manager.get_exit_code(&pid) {
-            context.set_rax(ret as usize);
-        } else {
-            manager.wait_pid(pid);
-            manager.save_current(context);
-            manager.current().write().block();
-            manager.switch_next(context);
+        match manager.try_wait(pid) {
+            Ok(Some((pid, ret))) => {
+                if status != 0 {
+                    uaccess::copy_to_user(status, &ret.to_ne_bytes());
+                }
+                context.set_result(Ok(pid.0 as usize));
+            }
+            Ok(None) if options & WNOHANG != 0 => context.set_result(Ok(0)),
+            Ok(None) => {
+                // issue the syscall again once a child has exited
+                manager.wait_pid(pid);
+                block_and_restart(context);
+            }
+            Err(errno) => context.set_result(Err(errno)),
         }
     })
 }
 
-pub fn fork(context: &mut ProcessContext) {
+pub fn sleep(ns: u64, context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
+        context.set_result(Ok(0));
+        if ns == 0 {
+            return;
+        }
+
         let manager = get_process_manager();
+        let deadline = clock::monotonic_ns().saturating_add(ns);
+        manager.sleep(deadline);
         manager.save_current(context);
-        let child_pid = manager.fork();
+        manager.current().write().block();
         manager.switch_next(context);
+    })
+}
+
+pub fn wake_up_sleepers(now: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().wake_up_sleepers(now);
+    })
+}
+
+/// Send `sig` to `pid`, `None` only checks that `pid` can be signaled
+pub fn send_signal(pid: ProcessId, sig: Option<Signal>) -> Result<(), Errno> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().send_signal(pid, sig)
+    })
+}
+
+/// Allow `pid` to access `count` I/O ports from `from` in user mode
+pub fn grant_io_ports(pid: ProcessId, from: usize, count: usize) -> Result<(), Errno> {
+    let ports = match from.checked_add(count) {
+        Some(end) if count > 0 && end <= gdt::IO_PORTS as usize => from as u32..end as u32,
+        _ => return Err(Errno::InvalidArgument),
+    };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().grant_io_ports(pid, ports)
+    })
+}
+
+/// Install `handler` for `sig` in the current process, returns the previous one
+///
+/// `trampoline` is the user entry point that runs handlers, see `signal::enter_handler`.
+pub fn sigaction(sig: Signal, handler: usize, trampoline: usize) -> Result<usize, Errno> {
+    if !sig.can_catch() {
+        return Err(Errno::InvalidArgument);
+    }
+
+    if handler != SIG_DFL && handler != SIG_IGN {
+        let in_user = |addr: usize| addr != 0 && (addr as u64) < uaccess::USER_SPACE_END;
+        if !in_user(handler) || !in_user(trampoline) {
+            return Err(Errno::Fault);
+        }
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let proc = get_process_manager().current();
+        let old = proc
+            .write()
+            .signals_mut()
+            .set_handler(sig, handler, trampoline);
+        Ok(old)
+    })
+}
+
+pub fn sigprocmask(how: SigMaskHow, set: SigSet) -> SigSet {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .current()
+            .write()
+            .signals_mut()
+            .set_mask(how, set)
+    })
+}
+
+pub fn sigreturn(frame_addr: usize, context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().sigreturn(frame_addr, context);
+    })
+}
+
+/// Deliver pending signals before returning to user mode
+pub fn handle_signals(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().handle_signals(context);
+    })
+}
+
+pub fn fork(context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        manager.save_current(context);
+        match manager.fork() {
+            Ok(_) => {
+                manager.switch_next(context);
+            }
+            Err(err) => context.set_result(Err(err)),
+        }
         // warn!("Forked process: {}#{}", manager.current().read().name(), child_pid);
     });
 }
 
-pub fn new_sem(key: u32, val: usize) -> usize {
+pub fn new_sem(key: u32, val: usize) -> Result<(), Errno> {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
-        let ret = manager.current().write().new_sem(key, val);
-        ret as usize
+        if manager.current().write().new_sem(key, val) {
+            Ok(())
+        } else {
+            Err(Errno::Exists)
+        }
     })
 }
 
-pub fn remove_sem(key: u32) -> usize {
+pub fn remove_sem(key: u32) -> Result<(), Errno> {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
-        let ret = manager.current().write().remove_sem(key);
-        ret as usize
+        if manager.current().write().remove_sem(key) {
+            Ok(())
+        } else {
+            Err(Errno::NotFound)
+        }
     })
 }
 
 pub fn sem_signal(key: u32, context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
-        let pid = processor::get_pid();
         let ret = manager.current().write().sem_signal(key);
         match ret {
-            SemaphoreResult::Ok => context.set_rax(0),
-            SemaphoreResult::NotExist => context.set_rax(1),
-            SemaphoreResult::WakeUp(pid) => manager.wake_up(pid, Some(0)),
+            SemaphoreResult::Ok => context.set_result(Ok(0)),
+            SemaphoreResult::NotExist => context.set_result(Err(Errno::NotFound)),
+            SemaphoreResult::WakeUp(pid) => {
+                context.set_result(Ok(0));
+                manager.wake_up(pid, Some(0));
+            }
             _ => unreachable!(),
         }
     })
@@ -252,8 +634,8 @@ pub fn sem_wait(key: u32, context: &mut ProcessContext) {
         let pid = processor::get_pid();
         let ret = manager.current().write().sem_wait(key, pid);
         match ret {
-            SemaphoreResult::Ok => context.set_rax(0),
-            SemaphoreResult::NotExist => context.set_rax(1),
+            SemaphoreResult::Ok => context.set_result(Ok(0)),
+            SemaphoreResult::NotExist => context.set_result(Err(Errno::NotFound)),
             SemaphoreResult::Block(pid) => {
                 // FIXME: save, block it, then switch to next
                 //        use `save_current` and `switch_next`
@@ -266,6 +648,124 @@ pub fn sem_wait(key: u32, context: &mut ProcessContext) {
     })
 }
 
+/// Map memory as described by `args`, returns the start of the region
+pub fn mmap(args: &MmapArgs) -> Result<usize, Errno> {
+    if args.flags & !(MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+
+    let flags = prot_flags(args.prot)?;
+
+    // shared mappings are not supported
+    if args.flags & MAP_PRIVATE == 0 {
+        return Err(Errno::NotSupported);
+    }
+
+    let len = (args.len as u64)
+        .checked_next_multiple_of(PAGE_SIZE)
+        .filter(|&len| len > 0 && len <= vm::mmap::MMAP_END - vm::mmap::MMAP_START)
+        .ok_or(Errno::InvalidArgument)?;
+    let addr = args.addr as u64;
+    let fixed = args.flags & MAP_FIXED != 0;
+
+    if fixed
+        && (addr % PAGE_SIZE != 0 || addr < vm::mmap::MMAP_START || addr > vm::mmap::MMAP_END - len)
+    {
+        return Err(Errno::InvalidArgument);
+    }
+    if args.offset as u64 % PAGE_SIZE != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let proc = get_process_manager().current();
+
+        // private file mappings get a copy of the file
+        let data = if args.flags & MAP_ANONYMOUS == 0 {
+            let fd = u8::try_from(args.fd).map_err(|_| Errno::BadFd)?;
+            proc.read().read_at(fd, args.offset, len as usize)?
+        } else {
+            Vec::new()
+        };
+
+        proc.write()
+            .mmap(addr, len, fixed, flags, &data)
+            .map(|addr| addr.as_u64() as usize)
+            .ok_or(Errno::NoMemory)
+    })
+}
+
+/// Page flags for the `PROT_*` protection `prot`, `None` for `PROT_NONE`
+///
+/// Writable pages are never executable (W^X).
+fn prot_flags(prot: usize) -> Result<Option<PageTableFlags>, Errno> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+        return Err(Errno::NotSupported);
+    }
+
+    Ok((prot != PROT_NONE).then(|| {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if prot & PROT_WRITE != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if prot & PROT_EXEC == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }))
+}
+
+/// Unmap the pages of `[addr, addr + len)` mapped by `mmap`
+pub fn munmap(addr: usize, len: usize) -> Result<(), Errno> {
+    let (addr, len) = (addr as u64, len as u64);
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(Errno::InvalidArgument)?;
+
+    if addr % PAGE_SIZE != 0 || len == 0 || addr.checked_add(len).is_none() {
+        return Err(Errno::InvalidArgument);
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().write().munmap(addr, len);
+        Ok(())
+    })
+}
+
+/// Change the protection of the pages of `[addr, addr + len)` to `prot`
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<(), Errno> {
+    let flags = prot_flags(prot)?;
+    let (addr, len) = (addr as u64, len as u64);
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(Errno::InvalidArgument)?;
+
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    if addr
+        .checked_add(len)
+        .is_none_or(|end| end > uaccess::USER_SPACE_END)
+    {
+        return Err(Errno::NoMemory);
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if get_process_manager()
+            .current()
+            .write()
+            .mprotect(addr, len, flags)
+        {
+            Ok(())
+        } else {
+            Err(Errno::NoMemory)
+        }
+    })
+}
+
 pub fn brk(addr: Option<VirtAddr>) -> Option<VirtAddr> {
     x86_64::instructions::interrupts::without_interrupts(|| {
         // NOTE: `brk` does not need to get write lock
@@ -276,7 +776,9 @@ pub fn brk(addr: Option<VirtAddr>) -> Option<VirtAddr> {
 #[inline]
 pub fn still_alive(pid: ProcessId) -> bool {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        let ret = get_process_manager().get_exit_code(&pid);
-        if let None = ret { true } else { false }
+        // children of the kernel process are dropped as soon as they exit
+        get_process_manager()
+            .get_proc_public(&pid)
+            .is_some_and(|proc| proc.read().exit_code().is_none())
     })
 }
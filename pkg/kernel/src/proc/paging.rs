@@ -6,7 +6,7 @@ use uefi::proto::debug;
 use x86_64::{
     VirtAddr,
     registers::control::{Cr3, Cr3Flags},
-    structures::paging::*,
+    structures::paging::{mapper::MapToError, *},
 };
 
 pub struct Cr3RegValue {
@@ -20,6 +20,7 @@ impl Cr3RegValue {
     }
 }
 
+#[derive(Clone)]
 pub struct PageTableContext {
     pub reg: Arc<Cr3RegValue>,
 }
@@ -33,13 +34,13 @@ impl PageTableContext {
     }
 
     /// Create a new page table object based on current page table.
-    pub fn clone_level_4(&self) -> Self {
+    pub fn clone_level_4(&self) -> Result<Self, MapToError<Size4KiB>> {
         // 1. alloc new page table
         let mut frame_alloc = crate::memory::get_frame_alloc_for_sure();
         info!("Allocating new page table...");
         let page_table_addr = frame_alloc
             .allocate_frame()
-            .expect("Cannot alloc page table for new process.");
+            .ok_or(MapToError::FrameAllocationFailed)?;
 
         // 2. copy current page table to new page table
         unsafe {
@@ -51,9 +52,9 @@ impl PageTableContext {
         }
 
         // 3. create page table object
-        Self {
+        Ok(Self {
             reg: Arc::new(Cr3RegValue::new(page_table_addr, Cr3Flags::empty())),
-        }
+        })
     }
 
     /// Load the page table to Cr3 register.
@@ -76,13 +77,6 @@ impl PageTableContext {
     pub fn using_count(&self) -> usize {
         Arc::strong_count(&self.reg)
     }
-
-    pub fn fork(&self) -> Self {
-        // forked process shares the page table
-        Self {
-            reg: self.reg.clone(),
-        }
-    }
 }
 
 impl core::fmt::Debug for PageTableContext {
@@ -1,13 +1,16 @@
 use super::*;
 use crate::humanized_size;
 use crate::memory::*;
+use crate::proc::fpu::FpuState;
+use crate::proc::signal::SignalState;
 use crate::proc::sync::*;
-use crate::proc::vm::ProcessVm;
 use crate::proc::vm::stack::*;
+use crate::proc::vm::{MemoryUsage, ProcessVm};
 use alloc::sync::Arc;
 use alloc::sync::Weak;
 use alloc::vec::Vec;
 use chrono::offset;
+use core::ops::Range;
 use spin::*;
 use x86_64::structures::paging::mapper::MapToError;
 use x86_64::structures::paging::page::PageRange;
@@ -26,10 +29,23 @@ pub struct ProcessInner {
     ticks_passed: usize,
     status: ProgramStatus,
     context: ProcessContext,
+    /// x87 / SSE / AVX registers, saved and restored with `context`
+    fpu: FpuState,
     exit_code: Option<isize>,
     proc_data: Option<ProcessData>,
     page_table: Option<PageTableContext>,
     proc_vm: Option<ProcessVm>,
+    signals: SignalState,
+    /// I/O ports granted to the process, none if `None`
+    io_bitmap: Option<gdt::IoBitmap>,
+    /// started by the kernel as a driver, may grant I/O ports
+    io_privileged: bool,
+    /// stack of the process in kernel mode, none for the kernel process
+    kernel_stack: Option<KernelStack>,
+    /// the saved context is in the middle of a syscall, see `block_in_place`
+    in_kernel: bool,
+    /// exit code of a kill that waits for the process to leave the kernel
+    pending_exit: Option<isize>,
 }
 
 impl Process {
@@ -58,19 +74,26 @@ impl Process {
 
         // create context
         let pid = ProcessId::new();
-        let page_table = proc_vm.as_ref().map(|vm| vm.page_table.clone_level_4());
+        let page_table = proc_vm.as_ref().map(|vm| vm.page_table.clone());
 
         let inner = ProcessInner {
             name,
             parent,
             status: ProgramStatus::Ready,
             context: ProcessContext::default(),
+            fpu: FpuState::new(),
             ticks_passed: 0,
             exit_code: None,
             children: Vec::new(),
             proc_vm: proc_vm,
             page_table: page_table,
             proc_data: Some(proc_data.unwrap_or_default()),
+            signals: SignalState::default(),
+            io_bitmap: None,
+            io_privileged: false,
+            kernel_stack: None,
+            in_kernel: false,
+            pending_exit: None,
         };
 
         trace!("New process {}#{} created.", &inner.name, pid);
@@ -92,10 +115,14 @@ impl Process {
             ret
         );
 
-        inner.kill(ret);
+        let proc_data = inner.kill(ret);
+        drop(inner);
+
+        // closing pipes wakes up other processes, do it without the lock
+        drop(proc_data);
     }
 
-    pub fn alloc_init_stack(&self) -> VirtAddr {
+    pub fn alloc_init_stack(&self) -> Result<VirtAddr, MapToError<Size4KiB>> {
         self.write().vm_mut().init_proc_stack(self.pid)
     }
 
@@ -103,19 +130,31 @@ impl Process {
         self.write().vm_mut().init_user_proc_stack(self.pid)
     }
 
-    pub fn fork(self: &Arc<Self>) -> Arc<Process> {
+    pub fn alloc_kernel_stack(&self) -> Result<(), MapToError<Size4KiB>> {
+        self.write().kernel_stack = Some(KernelStack::new(self.pid)?);
+        Ok(())
+    }
+
+    /// Fork the process, the child's address space is built in `page_table`
+    pub fn fork(
+        self: &Arc<Self>,
+        page_table: PageTableContext,
+    ) -> Result<Arc<Process>, MapToError<Size4KiB>> {
         // FIXME: lock inner as write
         let mut inner = self.write();
         // FIXME: inner fork with parent weak ref
         let parent = Arc::downgrade(self);
-        let child_inner = inner.fork(Some(parent));
+        let mut child_inner = inner.fork(Some(parent), page_table)?;
 
         // FOR DBG: maybe print the child process info
         //          e.g. parent, name, pid, etc.
 
+        let pid = ProcessId::new();
+        child_inner.kernel_stack = Some(KernelStack::new(pid)?);
+
         // FIXME: make the arc of child
         let child = Arc::new(Process {
-            pid: ProcessId::new(),
+            pid,
             inner: Arc::new(RwLock::new(child_inner)),
         });
         // FIXME: add child to current process's children list
@@ -123,7 +162,7 @@ impl Process {
         // FIXME: set fork ret value for parent with `context.set_rax`
         inner.context.set_rax(child.pid.0 as usize);
         drop(inner);
-        child
+        Ok(child)
     }
 }
 
@@ -152,11 +191,44 @@ impl ProcessInner {
         self.status = ProgramStatus::Blocked;
     }
 
+    /// Block the process with its saved context in the middle of a syscall
+    pub fn block_in_kernel(&mut self) {
+        self.status = ProgramStatus::Blocked;
+        self.in_kernel = true;
+    }
+
+    /// Whether the process is blocked in a syscall, or woken up but not
+    /// switched back to yet
+    pub fn is_in_kernel(&self) -> bool {
+        self.in_kernel
+    }
+
+    /// Kill the process once it returns from the syscall it is blocked in
+    pub fn set_pending_exit(&mut self, ret: isize) {
+        self.pending_exit = Some(ret);
+    }
+
+    pub fn exit_pending(&self) -> bool {
+        self.pending_exit.is_some()
+    }
+
+    pub fn take_pending_exit(&mut self) -> Option<isize> {
+        self.pending_exit.take()
+    }
+
     pub fn exit_code(&self) -> Option<isize> {
         self.exit_code
     }
 
-    pub fn clone_page_table(&self) -> PageTableContext {
+    pub fn signals(&self) -> &SignalState {
+        &self.signals
+    }
+
+    pub fn signals_mut(&mut self) -> &mut SignalState {
+        &mut self.signals
+    }
+
+    pub fn clone_page_table(&self) -> Result<PageTableContext, MapToError<Size4KiB>> {
         self.page_table.as_ref().unwrap().clone_level_4()
     }
 
@@ -172,15 +244,28 @@ impl ProcessInner {
         self.proc_vm.as_mut().unwrap()
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
+    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> Result<bool, MapToError<Size4KiB>> {
         self.vm_mut().handle_page_fault(addr)
     }
 
+    pub fn handle_cow_fault(&mut self, addr: VirtAddr) -> Result<bool, MapToError<Size4KiB>> {
+        self.proc_vm
+            .as_mut()
+            .map_or(Ok(false), |vm| vm.handle_cow_fault(addr))
+    }
+
+    pub fn check_user_range(&mut self, addr: VirtAddr, len: u64, write: bool) -> bool {
+        self.proc_vm
+            .as_mut()
+            .is_some_and(|vm| vm.check_user_range(addr, len, write))
+    }
+
     /// Save the process's context
     /// mark the process as ready
     pub(super) fn save(&mut self, context: &ProcessContext) {
         // FIXME: save the process's context
         self.context.save(context);
+        self.fpu.save();
         self.status = ProgramStatus::Ready;
     }
 
@@ -189,17 +274,99 @@ impl ProcessInner {
     pub(super) fn restore(&mut self, context: &mut ProcessContext) {
         // FIXME: restore the process's context
         self.context.restore(context);
+        self.fpu.restore();
         self.status = ProgramStatus::Running;
+        self.in_kernel = false;
 
         // FIXME: restore the process's page table
         self.vm().page_table.load();
+
+        gdt::load_io_bitmap(self.io_bitmap.as_ref());
+
+        if let Some(stack) = &self.kernel_stack {
+            gdt::set_kernel_stack(stack.top());
+        }
+    }
+
+    /// Allow the process to access `ports` from user mode
+    ///
+    /// The ports are kept across `exec`, a forked child starts without any.
+    pub fn grant_io_ports(&mut self, ports: Range<u32>) {
+        self.io_bitmap
+            .get_or_insert_with(gdt::IoBitmap::new)
+            .allow(ports);
+    }
+
+    pub fn io_bitmap(&self) -> Option<&gdt::IoBitmap> {
+        self.io_bitmap.as_ref()
+    }
+
+    /// Let the process grant I/O ports, only for drivers the kernel spawns
+    ///
+    /// A forked child starts without it, a reparented orphan does not gain it.
+    pub fn set_io_privileged(&mut self) {
+        self.io_privileged = true;
+    }
+
+    pub fn is_io_privileged(&self) -> bool {
+        self.io_privileged
+    }
+
+    /// Replace the process image with `proc_vm`, starting over at `entry`
+    ///
+    /// pid, parent, children, resources and the signal mask are kept,
+    /// the environment is replaced with `envp`.
+    pub fn exec(
+        &mut self,
+        name: String,
+        proc_vm: ProcessVm,
+        entry: VirtAddr,
+        stack_top: VirtAddr,
+        argv: &[String],
+        envp: &[String],
+    ) {
+        // leave the old page table before it is freed with the old vm
+        proc_vm.page_table.load();
+
+        self.name = name.to_ascii_lowercase();
+        self.page_table = Some(proc_vm.page_table.clone());
+        self.proc_vm = Some(proc_vm);
+        self.set_envs(envp);
+        self.signals.exec();
+
+        self.context = ProcessContext::default();
+        self.fpu = FpuState::new();
+        self.init_user_stack_frame(entry, stack_top, argv, envp);
     }
 
     pub fn parent(&self) -> Option<Arc<Process>> {
         self.parent.as_ref().and_then(|p| p.upgrade())
     }
 
-    pub fn kill(&mut self, ret: isize) {
+    pub fn set_parent(&mut self, parent: Weak<Process>) {
+        self.parent = Some(parent);
+    }
+
+    pub fn children(&self) -> &[Arc<Process>] {
+        &self.children
+    }
+
+    pub fn add_child(&mut self, child: Arc<Process>) {
+        self.children.push(child);
+    }
+
+    pub fn take_children(&mut self) -> Vec<Arc<Process>> {
+        core::mem::take(&mut self.children)
+    }
+
+    pub fn remove_child(&mut self, pid: ProcessId) -> Option<Arc<Process>> {
+        let idx = self.children.iter().position(|c| c.pid() == pid)?;
+        Some(self.children.remove(idx))
+    }
+
+    /// Mark the process as dead and free its memory,
+    /// the process data is returned to be dropped by the caller
+    pub fn kill(&mut self, ret: isize) -> Option<ProcessData> {
         // FIXME: set exit code
         self.exit_code = Some(ret);
 
@@ -209,55 +376,67 @@ impl ProcessInner {
         // FIXME: take and drop unused resources
         self.page_table.take();
         self.proc_vm.take();
-        self.proc_data.take();
+        self.proc_data.take()
     }
 
     pub fn init_stack_frame(&mut self, entry: VirtAddr, stack_top: VirtAddr) {
         self.context.init_stack_frame(entry, stack_top);
     }
 
-    pub fn init_user_stack_frame(&mut self, entry: VirtAddr, stack_top: VirtAddr) {
-        self.context.init_user_stack_frame(entry, stack_top);
+    /// Start at `entry` with `argv` and `envp` copied below `stack_top`
+    pub fn init_user_stack_frame(
+        &mut self,
+        entry: VirtAddr,
+        stack_top: VirtAddr,
+        argv: &[String],
+        envp: &[String],
+    ) {
+        let args = self.vm_mut().init_user_args(stack_top, argv, envp);
+        self.context.init_user_stack_frame(entry, args.stack_top);
+        self.context.set_args(args.argc, args.argv, args.envp);
     }
 
-    pub fn load_elf(&mut self, elf: &ElfFile) {
-        self.proc_vm.as_mut().unwrap().load_elf(elf);
+    pub fn load_elf(&mut self, elf: &ElfFile<'static>) -> Result<(), MapToError<Size4KiB>> {
+        self.proc_vm.as_mut().unwrap().load_elf(elf)
     }
 
-    pub fn fork(&self, parent: Option<Weak<Process>>) -> ProcessInner {
+    pub fn fork(
+        &self,
+        parent: Option<Weak<Process>>,
+        page_table: PageTableContext,
+    ) -> Result<ProcessInner, MapToError<Size4KiB>> {
         // FIXME: fork the process virtual memory struct
-        // FIXME: calculate the real stack offset
-        // FIXME: update `rsp` in interrupt stack frame
-        // Calculate the stack offset
-        let stack_offset_count = self.children.len() as u64;
-        let child_vm = self.proc_vm.as_ref().unwrap().fork(stack_offset_count);
-        let child_page_table = child_vm.page_table.clone_level_4();
-
-        let child_stack_bot = child_vm.stack.range().start.start_address().as_u64();
-        let parent_stack_bot = self.vm().stack.range().start.start_address().as_u64();
-        let offset = child_stack_bot - parent_stack_bot;
+        // the child runs on the same addresses, its stack is copied on write
+        let child_vm = self.proc_vm.as_ref().unwrap().fork(page_table)?;
+        let child_page_table = child_vm.page_table.clone();
 
         // FIXME: set the return value 0 for child with `context.set_rax`
         let mut child_ctx: ProcessContext = self.context;
-        child_ctx.set_rsp_offset(offset);
         child_ctx.set_rax(0);
 
         // FIXME: clone the process data struct
-        let child_proc_data = self.proc_data.as_ref().map(|data| data.clone());
+        let child_proc_data = self.proc_data.as_ref().map(|data| data.fork());
 
         // FIXME: construct the child process inner
-        ProcessInner {
+        Ok(ProcessInner {
             name: self.name.clone(),
             parent,
             children: Vec::new(),
             ticks_passed: 0,
             status: ProgramStatus::Ready,
             context: child_ctx,
+            fpu: self.fpu.clone(),
             exit_code: None,
-            proc_data: self.proc_data.clone(),
+            proc_data: child_proc_data,
             page_table: Some(child_page_table),
             proc_vm: Some(child_vm),
-        }
+            signals: self.signals.fork(),
+            io_bitmap: None,
+            io_privileged: false,
+            kernel_stack: None,
+            in_kernel: false,
+            pending_exit: None,
+        })
         // NOTE: return inner because there's no pid record in inner
     }
 
@@ -268,6 +447,25 @@ impl ProcessInner {
     pub fn brk(&self, addr: Option<VirtAddr>) -> Option<VirtAddr> {
         self.proc_vm.as_ref().unwrap().brk(addr)
     }
+
+    pub fn mmap(
+        &mut self,
+        addr: u64,
+        len: u64,
+        fixed: bool,
+        flags: Option<PageTableFlags>,
+        data: &[u8],
+    ) -> Option<VirtAddr> {
+        self.vm_mut().mmap(addr, len, fixed, flags, data)
+    }
+
+    pub fn munmap(&mut self, addr: u64, len: u64) {
+        self.vm_mut().munmap(addr, len);
+    }
+
+    pub fn mprotect(&mut self, addr: u64, len: u64, flags: Option<PageTableFlags>) -> bool {
+        self.vm_mut().mprotect(addr, len, flags)
+    }
 }
 
 impl core::ops::Deref for Process {
@@ -309,6 +507,7 @@ impl core::fmt::Debug for Process {
             .field("status", &inner.status)
             .field("context", &inner.context)
             .field("vm", &inner.proc_vm)
+            .field("kernel_stack", &inner.kernel_stack)
             .finish()
     }
 }
@@ -316,16 +515,23 @@ impl core::fmt::Debug for Process {
 impl core::fmt::Display for Process {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         let inner = self.inner.read();
-        let (size, unit) = humanized_size(inner.proc_vm.as_ref().map_or(0, |vm| vm.memory_usage()));
+        let usage = inner
+            .proc_vm
+            .as_ref()
+            .map_or(MemoryUsage::default(), |vm| vm.memory_usage());
+        let (resident, resident_unit) = humanized_size(usage.resident);
+        let (reserved, reserved_unit) = humanized_size(usage.reserved);
         write!(
             f,
-            " #{:-3} | #{:-3} | {:12} | {:7} | {:>5.1} {} | {:?}",
+            " #{:-3} | #{:-3} | {:12} | {:7} | {:>5.1} {:3} / {:>5.1} {:3} | {:?}",
             self.pid.0,
             inner.parent().map(|p| p.pid.0).unwrap_or(0),
             inner.name,
             inner.ticks_passed,
-            size,
-            unit,
+            resident,
+            resident_unit,
+            reserved,
+            reserved_unit,
             inner.status
         )?;
         Ok(())
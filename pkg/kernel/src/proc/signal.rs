@@ -0,0 +1,179 @@
+use syscall_def::signal::*;
+
+use super::context::ProcessContextValue;
+use super::{ProcessContext, uaccess};
+
+/// Number of signal slots, signal numbers are below this
+const NSIG: usize = 32;
+
+/// How a signal is handled when it is sent or delivered
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Disposition {
+    Ignore,
+    Terminate,
+    /// Run the user handler at this address
+    Catch(usize),
+}
+
+/// Saved state pushed on the user stack while a handler runs,
+/// `SigReturn` restores it
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalFrame {
+    pub context: ProcessContextValue,
+    pub blocked: u64,
+}
+
+/// Signal state of a process
+#[derive(Clone)]
+pub struct SignalState {
+    pending: SigSet,
+    blocked: SigSet,
+    handlers: [usize; NSIG],
+    /// user entry point that calls the handler and then `SigReturn`
+    trampoline: usize,
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self {
+            pending: SigSet::empty(),
+            blocked: SigSet::empty(),
+            handlers: [SIG_DFL; NSIG],
+            trampoline: 0,
+        }
+    }
+}
+
+impl SignalState {
+    pub fn disposition(&self, sig: Signal) -> Disposition {
+        if !sig.can_catch() {
+            return Disposition::Terminate;
+        }
+
+        match self.handlers[sig as usize] {
+            SIG_IGN => Disposition::Ignore,
+            SIG_DFL => match sig.default_action() {
+                DefaultAction::Terminate => Disposition::Terminate,
+                DefaultAction::Ignore => Disposition::Ignore,
+            },
+            handler => Disposition::Catch(handler),
+        }
+    }
+
+    #[inline]
+    pub fn is_blocked(&self, sig: Signal) -> bool {
+        sig.can_catch() && self.blocked.contains(sig)
+    }
+
+    #[inline]
+    pub fn blocked(&self) -> SigSet {
+        self.blocked
+    }
+
+    #[inline]
+    pub fn trampoline(&self) -> usize {
+        self.trampoline
+    }
+
+    pub fn raise(&mut self, sig: Signal) {
+        self.pending.insert(sig);
+    }
+
+    /// Take the lowest pending signal that is not blocked
+    pub fn take_pending(&mut self) -> Option<Signal> {
+        let sig = self.pending.difference(self.blocked).first()?;
+        self.pending.remove(sig);
+        Some(sig)
+    }
+
+    /// Install `handler` for `sig`, returns the previous handler
+    pub fn set_handler(&mut self, sig: Signal, handler: usize, trampoline: usize) -> usize {
+        if handler != SIG_DFL && handler != SIG_IGN {
+            self.trampoline = trampoline;
+        }
+
+        // an ignored signal is discarded, even when it is already pending
+        if handler == SIG_IGN {
+            self.pending.remove(sig);
+        }
+
+        core::mem::replace(&mut self.handlers[sig as usize], handler)
+    }
+
+    /// Change the blocked set, returns the previous one
+    pub fn set_mask(&mut self, how: SigMaskHow, set: SigSet) -> SigSet {
+        let old = self.blocked;
+        let blocked = match how {
+            SigMaskHow::Block => old.union(set),
+            SigMaskHow::Unblock => old.difference(set),
+            SigMaskHow::SetMask => set,
+        };
+        self.blocked = blocked.difference(Signal::Kill.into());
+        old
+    }
+
+    /// State inherited by a forked child, nothing is pending yet
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: SigSet::empty(),
+            ..self.clone()
+        }
+    }
+
+    /// Handlers point into the old image, reset them to the default action
+    pub fn exec(&mut self) {
+        for handler in self.handlers.iter_mut() {
+            if *handler != SIG_IGN {
+                *handler = SIG_DFL;
+            }
+        }
+        self.trampoline = 0;
+    }
+}
+
+/// Size of the red zone below the user stack pointer that must be preserved
+const RED_ZONE: usize = 128;
+
+/// Save `frame` on the user stack and enter the handler for `sig`
+///
+/// The trampoline is called with the signal number, the handler and the
+/// frame address, and never returns. Returns false if the user stack
+/// cannot hold the frame.
+pub fn enter_handler(
+    context: &mut ProcessContext,
+    frame: &SignalFrame,
+    sig: Signal,
+    handler: usize,
+    trampoline: usize,
+) -> bool {
+    let sp = context.stack_frame.stack_pointer.as_u64() as usize;
+    let frame_addr = match sp.checked_sub(RED_ZONE + size_of::<SignalFrame>()) {
+        Some(addr) if addr >= 0x10 => addr & !0xf,
+        _ => return false,
+    };
+    // a fake return address, so the trampoline starts with an aligned stack
+    let stack_top = frame_addr - size_of::<usize>();
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            frame as *const SignalFrame as *const u8,
+            size_of::<SignalFrame>(),
+        )
+    };
+
+    if !uaccess::copy_to_user(frame_addr, bytes)
+        || !uaccess::copy_to_user(stack_top, &0usize.to_ne_bytes())
+    {
+        return false;
+    }
+
+    context.enter_signal_handler(trampoline, stack_top, [sig as usize, handler, frame_addr]);
+    true
+}
+
+/// Read the frame saved by `enter_handler` back from user memory
+pub fn read_frame(frame_addr: usize) -> Option<SignalFrame> {
+    let buf = uaccess::copy_from_user(frame_addr, size_of::<SignalFrame>())?;
+    Some(unsafe { (buf.as_ptr() as *const SignalFrame).read_unaligned() })
+}
@@ -0,0 +1,183 @@
+//! Access user memory from the kernel
+//!
+//! Syscall handlers must never dereference raw user pointers directly,
+//! every buffer is checked against the current process's page table
+//! before it is copied in or out of the kernel.
+//!
+//! With SMAP the kernel faults on any access to user pages, the copies
+//! below are the only places that lift it for the time of the access.
+
+use super::manager::get_process_manager;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86::cpuid::CpuId;
+use x86_64::VirtAddr;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::PageFaultErrorCode;
+
+/// The end of the lower half of the address space, user memory lives below
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// The longest path accepted from user space
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Whether SMAP is enabled, `stac` and `clac` are invalid opcodes without it
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable SMEP and SMAP if the CPU supports them
+///
+/// The kernel then faults when it executes a user page, or accesses one
+/// outside of a `UserAccess` guard.
+pub fn init() {
+    let features = CpuId::new().get_extended_feature_info();
+    let smep = features.as_ref().is_some_and(|f| f.has_smep());
+    let smap = features.as_ref().is_some_and(|f| f.has_smap());
+
+    let mut flags = Cr4Flags::empty();
+    if smep {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if smap {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+    SMAP_ENABLED.store(smap, Ordering::Relaxed);
+
+    info!("SMEP {}, SMAP {}.", enabled(smep), enabled(smap));
+}
+
+fn enabled(on: bool) -> &'static str {
+    if on { "Enabled" } else { "Not Supported" }
+}
+
+/// Lifts SMAP until dropped, so the kernel can access user pages
+struct UserAccess;
+
+impl UserAccess {
+    fn begin() -> Self {
+        if SMAP_ENABLED.load(Ordering::Relaxed) {
+            unsafe { x86::bits32::eflags::stac() };
+        }
+        Self
+    }
+}
+
+impl Drop for UserAccess {
+    fn drop(&mut self) {
+        deny_user_access();
+    }
+}
+
+/// Forbid accesses to user pages again
+///
+/// The AC flag is kept from user space when entering the kernel, it must be
+/// cleared before a syscall is handled.
+pub fn deny_user_access() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { x86::bits32::eflags::clac() };
+    }
+}
+
+/// The protection that caused a kernel mode page fault at `addr`, if any
+///
+/// `flags` are the flags of the faulting code, an access with AC set comes
+/// from a `UserAccess` guard and is left to the page fault handler.
+pub fn protection_violation(
+    addr: u64,
+    err_code: PageFaultErrorCode,
+    flags: RFlags,
+) -> Option<&'static str> {
+    if addr >= USER_SPACE_END || !err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return None;
+    }
+
+    if err_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        Cr4::read()
+            .contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION)
+            .then_some("SMEP")
+    } else {
+        (SMAP_ENABLED.load(Ordering::Relaxed) && !flags.contains(RFlags::ALIGNMENT_CHECK))
+            .then_some("SMAP")
+    }
+}
+
+/// Check that `[addr, addr + len)` is mapped and accessible to the current
+/// process, and writable if `write` is set.
+pub fn check_user_range(addr: usize, len: usize, write: bool) -> bool {
+    if len == 0 {
+        return true;
+    }
+
+    // reject null pointers and ranges reaching into kernel space
+    match (addr as u64).checked_add(len as u64) {
+        Some(end) if addr != 0 && end <= USER_SPACE_END => {}
+        _ => return false,
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().write().check_user_range(
+            VirtAddr::new(addr as u64),
+            len as u64,
+            write,
+        )
+    })
+}
+
+/// Copy `len` bytes at user address `addr` into a kernel buffer
+pub fn copy_from_user(addr: usize, len: usize) -> Option<Vec<u8>> {
+    if !check_user_range(addr, len, false) {
+        return None;
+    }
+
+    let mut buf = Vec::with_capacity(len);
+    if len > 0 {
+        let _access = UserAccess::begin();
+        unsafe {
+            core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), len);
+            buf.set_len(len);
+        }
+    }
+
+    Some(buf)
+}
+
+/// Copy `buf` into user memory at `addr`
+pub fn copy_to_user(addr: usize, buf: &[u8]) -> bool {
+    if !check_user_range(addr, buf.len(), true) {
+        return false;
+    }
+
+    if !buf.is_empty() {
+        let _access = UserAccess::begin();
+        unsafe {
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), addr as *mut u8, buf.len());
+        }
+    }
+
+    true
+}
+
+/// Copy a UTF-8 string of `len` bytes at user address `addr`
+pub fn copy_str_from_user(addr: usize, len: usize) -> Option<String> {
+    if len > MAX_PATH_LEN {
+        return None;
+    }
+
+    String::from_utf8(copy_from_user(addr, len)?).ok()
+}
+
+/// Copy `len` bytes of NUL-terminated UTF-8 strings at user address `addr`
+pub fn copy_strs_from_user(addr: usize, len: usize) -> Option<Vec<String>> {
+    let buf = copy_from_user(addr, len)?;
+    let buf = buf.strip_suffix(&[0]).unwrap_or(&buf);
+    if buf.is_empty() {
+        return Some(Vec::new());
+    }
+
+    buf.split(|&b| b == 0)
+        .map(|s| String::from_utf8(s.to_vec()).ok())
+        .collect()
+}
@@ -3,11 +3,16 @@ use core::sync::atomic::{AtomicU64, Ordering};
 use alloc::sync::Arc;
 use uefi::proto::debug;
 use x86_64::{
-    VirtAddr,
-    structures::paging::{Page, mapper::UnmapError},
+    VirtAddr, align_up,
+    structures::paging::{
+        Page, PageTableFlags, Size4KiB,
+        mapper::{MapToError, UnmapError},
+        page::PageRangeInclusive,
+    },
 };
 
-use super::{FrameAllocatorRef, MapperRef};
+use super::{FrameAllocatorRef, MapperRef, map_zeroed_page, unmap_mapped};
+use crate::memory::PAGE_SIZE;
 
 // user process runtime heap
 // 0x100000000 bytes -> 4GiB
@@ -17,9 +22,17 @@ pub const HEAP_PAGES: u64 = 0x100000;
 pub const HEAP_SIZE: u64 = HEAP_PAGES * crate::memory::PAGE_SIZE;
 pub const HEAP_END: u64 = HEAP_START + HEAP_SIZE - 8;
 
+/// Flags of the heap pages
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
 /// User process runtime heap
 ///
 /// always page aligned, the range is [base, end)
+///
+/// `brk` only moves the end, pages are backed by zeroed frames on the first access.
 pub struct Heap {
     /// the base address of the heap
     ///
@@ -30,6 +43,9 @@ pub struct Heap {
     ///
     /// use atomic to allow multiple threads to access the heap
     end: Arc<AtomicU64>,
+
+    /// the number of pages backed by frames
+    resident: AtomicU64,
 }
 
 impl Heap {
@@ -37,14 +53,33 @@ impl Heap {
         Self {
             base: VirtAddr::new(HEAP_START),
             end: Arc::new(AtomicU64::new(HEAP_START)),
+            resident: AtomicU64::new(0),
         }
     }
 
+    /// The heap of a forked process, its end moves on its own
     pub fn fork(&self) -> Self {
         Self {
             base: self.base,
-            end: self.end.clone(),
+            end: Arc::new(AtomicU64::new(self.end.load(Ordering::Relaxed))),
+            resident: AtomicU64::new(self.resident.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// The pages of the heap, if any
+    pub(super) fn range(&self) -> Option<PageRangeInclusive> {
+        self.pages(self.base.as_u64(), self.end.load(Ordering::Relaxed))
+    }
+
+    /// The pages holding `[start, end)`
+    fn pages(&self, start: u64, end: u64) -> Option<PageRangeInclusive> {
+        if start >= end {
+            return None;
         }
+
+        let start_page = Page::containing_address(VirtAddr::new(start));
+        let end_page = Page::containing_address(VirtAddr::new(end - 1));
+        Some(Page::range_inclusive(start_page, end_page))
     }
 
     pub fn brk(
@@ -79,8 +114,8 @@ impl Heap {
         if new_end == current_end {
             return Some(VirtAddr::new(current_end)); // no change needed
         }
-        let diff = new_end - current_end;
-        let diff_pages = diff / crate::memory::PAGE_SIZE;
+        let diff = new_end as i64 - current_end as i64;
+        let diff_pages = diff / PAGE_SIZE as i64;
 
         // NOTE: print the heap difference for debugging
         trace!(
@@ -88,23 +123,22 @@ impl Heap {
             current_end, new_end, diff, diff_pages
         );
 
-        // FIXME: do the actual mapping or unmapping
-        if diff > 0 {
-            // growing the heap
-            let start_page = Page::containing_address(VirtAddr::new(current_end));
-            let end_page = Page::containing_address(VirtAddr::new(new_end));
-            let range = Page::range_inclusive(start_page, end_page);
-
-            // map the new pages
-            elf::map_range(range, mapper, alloc, true).unwrap();
+        // growing the heap maps nothing, its pages are backed on the first access,
+        // but it is refused if they could not all be backed right now
+        if new_end > current_end {
+            let pages =
+                (align_up(new_end, PAGE_SIZE) - align_up(current_end, PAGE_SIZE)) / PAGE_SIZE;
+            if pages as usize > alloc.frames_total() - alloc.frames_used() {
+                debug!("Heap brk: not enough memory for {} more pages", pages);
+                return None;
+            }
         } else {
-            // shrinking the heap
-            let start_page = Page::containing_address(VirtAddr::new(new_end));
-            let end_page = Page::containing_address(VirtAddr::new(current_end));
-            let range = Page::range_inclusive(start_page, end_page);
-
-            // unmap the old pages
-            elf::unmap_range(range, mapper, alloc, true).unwrap();
+            // shrinking the heap, free the pages that are no longer used
+            let start = align_up(new_end, PAGE_SIZE);
+            if let Some(range) = self.pages(start, current_end) {
+                let freed = unmap_mapped(range, mapper, alloc);
+                self.resident.fetch_sub(freed, Ordering::Relaxed);
+            }
         }
 
         // FIXME: update the end address
@@ -118,25 +152,45 @@ impl Heap {
         mapper: MapperRef,
         dealloc: FrameAllocatorRef,
     ) -> Result<(), UnmapError> {
-        if self.memory_usage() == 0 {
-            return Ok(());
-        }
-
         // FIXME: load the current end address and **reset it to base** (use `swap`)
         let current_end = self.end.swap(self.base.as_u64(), Ordering::Relaxed);
 
         // FIXME: unmap the heap pages
-        let start_page = Page::containing_address(self.base);
-        let end_page = Page::containing_address(VirtAddr::new(current_end));
-        let range = Page::range_inclusive(start_page, end_page);
-
-        elf::unmap_range(range, mapper, dealloc, true);
+        if let Some(range) = self.pages(self.base.as_u64(), current_end) {
+            unmap_mapped(range, mapper, dealloc);
+        }
+        self.resident.store(0, Ordering::Relaxed);
 
         Ok(())
     }
 
+    /// Back the page at `addr` with a zeroed frame if it is below the end of the heap,
+    /// returns false if it is not
+    pub fn handle_page_fault(
+        &self,
+        addr: VirtAddr,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<bool, MapToError<Size4KiB>> {
+        let end = align_up(self.end.load(Ordering::Relaxed), PAGE_SIZE);
+        if addr < self.base || addr.as_u64() >= end {
+            return Ok(false);
+        }
+
+        let page = Page::containing_address(addr);
+        map_zeroed_page(page, HEAP_FLAGS, 0, &[], mapper, alloc)?;
+        self.resident.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Bytes backed by frames
     pub fn memory_usage(&self) -> u64 {
-        self.end.load(Ordering::Relaxed) - self.base.as_u64()
+        self.resident.load(Ordering::Relaxed) * PAGE_SIZE
+    }
+
+    /// Bytes reserved by `brk`, rounded up to pages
+    pub fn reserved(&self) -> u64 {
+        align_up(self.end.load(Ordering::Relaxed), PAGE_SIZE) - self.base.as_u64()
     }
 }
 
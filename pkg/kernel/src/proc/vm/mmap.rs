@@ -0,0 +1,244 @@
+//! Memory regions mapped by `Mmap`
+//!
+//! Regions are placed in a fixed window between the heap and the stack.
+//! Their pages are backed by zeroed frames on the first access, except for
+//! the part of a private file mapping that is copied from the file.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        Page, PageTableFlags, Size4KiB, mapper::MapToError, page::PageRangeInclusive,
+    },
+};
+
+use super::{FrameAllocatorRef, MapperRef, map_zeroed_page, unmap_mapped};
+use crate::memory::PAGE_SIZE;
+
+// 0xf00_0000_0000 bytes -> 15TiB
+// from 0x0000_3000_0000_0000 to 0x0000_3eff_ffff_ffff
+pub const MMAP_START: u64 = 0x3000_0000_0000;
+pub const MMAP_END: u64 = 0x3f00_0000_0000;
+
+/// A mapped region, page aligned, the range is [start, end)
+#[derive(Clone)]
+pub struct Vma {
+    pub start: u64,
+    pub end: u64,
+    /// flags of the pages of the region, `None` if they cannot be accessed
+    pub flags: Option<PageTableFlags>,
+}
+
+impl Vma {
+    fn pages(&self) -> PageRangeInclusive {
+        Page::range_inclusive(
+            Page::containing_address(VirtAddr::new(self.start)),
+            Page::containing_address(VirtAddr::new(self.end - 1)),
+        )
+    }
+}
+
+/// Regions mapped by a process, they never overlap
+#[derive(Clone, Default)]
+pub struct MemoryMaps {
+    /// regions by their start address
+    vmas: BTreeMap<u64, Vma>,
+    /// number of pages backed by frames
+    usage: u64,
+}
+
+impl MemoryMaps {
+    /// Find room for `len` bytes, at `hint` if it is free
+    pub fn find_free(&self, hint: u64, len: u64) -> Option<u64> {
+        if hint % PAGE_SIZE == 0
+            && hint >= MMAP_START
+            && hint.checked_add(len).is_some_and(|end| end <= MMAP_END)
+            && self.is_free(hint, hint + len)
+        {
+            return Some(hint);
+        }
+
+        let mut start = MMAP_START;
+        for vma in self.vmas.values() {
+            if vma.start >= start + len {
+                break;
+            }
+            start = start.max(vma.end);
+        }
+
+        (start + len <= MMAP_END).then_some(start)
+    }
+
+    fn is_free(&self, start: u64, end: u64) -> bool {
+        self.vmas
+            .range(..end)
+            .next_back()
+            .is_none_or(|(_, vma)| vma.end <= start)
+    }
+
+    /// The region containing `addr`
+    pub fn find(&self, addr: u64) -> Option<&Vma> {
+        self.vmas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| addr < vma.end)
+    }
+
+    /// Map the free region `[start, start + len)`, the first pages are
+    /// filled with `data` right away, the rest on the first access
+    ///
+    /// The pages of `data` are backed even if the region is inaccessible,
+    /// so that they keep the contents once `mprotect` allows them.
+    pub fn map(
+        &mut self,
+        start: u64,
+        len: u64,
+        flags: Option<PageTableFlags>,
+        data: &[u8],
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let vma = Vma {
+            start,
+            end: start + len,
+            flags,
+        };
+        self.vmas.insert(start, vma.clone());
+
+        // same as `mprotect` with `PROT_NONE`, present for the kernel only
+        let flags = flags.unwrap_or(PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE);
+
+        for (page, chunk) in vma.pages().zip(data.chunks(PAGE_SIZE as usize)) {
+            if let Err(err) = self.map_page(page, flags, chunk, mapper, alloc) {
+                self.unmap(start, start + len, mapper, alloc);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Back `page` with a new frame holding `data`, zero filled
+    fn map_page(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+        data: &[u8],
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        map_zeroed_page(page, flags, 0, data, mapper, alloc)?;
+        self.usage += 1;
+        Ok(())
+    }
+
+    /// Unmap `[start, end)`, regions partly inside are split
+    pub fn unmap(&mut self, start: u64, end: u64, mapper: MapperRef, dealloc: FrameAllocatorRef) {
+        for vma in self.split(start, end) {
+            // pages that were never touched are not mapped
+            self.usage -= unmap_mapped(vma.pages(), mapper, dealloc);
+        }
+    }
+
+    /// Set the flags of the regions in `[start, end)`, regions partly inside are split
+    ///
+    /// Only the regions are updated, the flags of mapped pages are left to the caller.
+    pub fn protect(&mut self, start: u64, end: u64, flags: Option<PageTableFlags>) {
+        for vma in self.split(start, end) {
+            self.vmas.insert(vma.start, Vma { flags, ..vma });
+        }
+    }
+
+    /// Take the parts of the regions inside `[start, end)` out of the map,
+    /// the parts outside of it are kept
+    fn split(&mut self, start: u64, end: u64) -> Vec<Vma> {
+        let overlapping: Vec<u64> = self
+            .vmas
+            .range(..end)
+            .filter(|(_, vma)| vma.end > start)
+            .map(|(&addr, _)| addr)
+            .collect();
+
+        let mut inside = Vec::with_capacity(overlapping.len());
+        for addr in overlapping {
+            let Some(vma) = self.vmas.remove(&addr) else {
+                continue;
+            };
+
+            if vma.start < start {
+                let head = Vma {
+                    end: start,
+                    ..vma.clone()
+                };
+                self.vmas.insert(head.start, head);
+            }
+            if vma.end > end {
+                let tail = Vma {
+                    start: end,
+                    ..vma.clone()
+                };
+                self.vmas.insert(tail.start, tail);
+            }
+
+            inside.push(Vma {
+                start: vma.start.max(start),
+                end: vma.end.min(end),
+                ..vma
+            });
+        }
+
+        inside
+    }
+
+    /// Back the page at `addr` with a zeroed frame if it is in an accessible region,
+    /// returns false if it is not
+    pub fn handle_page_fault(
+        &mut self,
+        addr: VirtAddr,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<bool, MapToError<Size4KiB>> {
+        let Some(flags) = self.find(addr.as_u64()).and_then(|vma| vma.flags) else {
+            return Ok(false);
+        };
+
+        let page = Page::containing_address(addr);
+        self.map_page(page, flags, &[], mapper, alloc)?;
+        Ok(true)
+    }
+
+    /// Page ranges of all regions
+    pub fn ranges(&self) -> impl Iterator<Item = PageRangeInclusive> + '_ {
+        self.vmas.values().map(Vma::pages)
+    }
+
+    pub fn memory_usage(&self) -> u64 {
+        self.usage * PAGE_SIZE
+    }
+
+    /// Bytes of all regions, backed or not
+    pub fn reserved(&self) -> u64 {
+        self.vmas.values().map(|vma| vma.end - vma.start).sum()
+    }
+
+    pub fn clean_up(&mut self, mapper: MapperRef, dealloc: FrameAllocatorRef) {
+        self.unmap(MMAP_START, MMAP_END, mapper, dealloc);
+    }
+}
+
+impl core::fmt::Debug for Vma {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:#x}..{:#x} ", self.start, self.end)?;
+        match self.flags {
+            Some(flags) => write!(f, "{:?}", flags),
+            None => write!(f, "PROT_NONE"),
+        }
+    }
+}
+
+impl core::fmt::Debug for MemoryMaps {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_list().entries(self.vmas.values()).finish()
+    }
+}
@@ -1,20 +1,25 @@
 use crate::{humanized_size, memory::*};
-use alloc::{format, vec::Vec};
+use alloc::{format, string::String, vec, vec::Vec};
 use x86_64::{
     VirtAddr,
     structures::paging::{
-        mapper::{CleanUp, UnmapError},
+        mapper::{CleanUp, MapToError, MappedFrame, TranslateResult, UnmapError},
         page::*,
+        page_table::PageTableEntry,
         *,
     },
 };
 use xmas_elf::ElfFile;
 
 pub mod heap;
+pub mod mmap;
+pub mod segment;
 pub mod stack;
 
 use super::{PageTableContext, ProcessId};
 use crate::proc::vm::heap::*;
+use crate::proc::vm::mmap::*;
+use crate::proc::vm::segment::*;
 use crate::proc::vm::stack::*;
 use elf::{map_pages, unmap_pages};
 
@@ -26,8 +31,35 @@ use boot::KernelPages;
 type MapperRef<'a> = &'a mut OffsetPageTable<'static>;
 type FrameAllocatorRef<'a> = &'a mut BootInfoFrameAllocator;
 
+/// Marks a writable page shared with a forked process, it is mapped
+/// read-only until the first write gives it its own copy
+pub const COW_FLAG: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Flags of the page tables created for user pages
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Memory of a process in bytes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryUsage {
+    /// memory backed by frames
+    pub resident: u64,
+    /// address space reserved for the process, resident or not
+    pub reserved: u64,
+}
+
+/// Location of the arguments copied by `ProcessVm::init_user_args`
+pub struct UserArgs {
+    pub stack_top: VirtAddr,
+    pub argc: usize,
+    pub argv: VirtAddr,
+    pub envp: VirtAddr,
+}
+
 pub struct ProcessVm {
-    // page table is shared by parent and child
+    // page table is owned by the process,
+    // frames are shared copy-on-write with forked processes
     pub(super) page_table: PageTableContext,
 
     // stack is pre-process allocated
@@ -36,10 +68,15 @@ pub struct ProcessVm {
     // heap is allocated by brk syscall
     pub(super) heap: Heap,
 
-    // code is hold by the first process
-    // these fields will be empty for other processes
+    // regions mapped by mmap syscall
+    pub(super) mmaps: MemoryMaps,
+
+    // code is loaded by `load_elf`, forked processes share its frames
     pub(super) code: Vec<PageRangeInclusive>,
     pub(super) code_usage: u64,
+
+    // segments of the user program, their pages are loaded on the first access
+    pub(super) segments: Vec<Segment>,
 }
 
 impl ProcessVm {
@@ -48,8 +85,10 @@ impl ProcessVm {
             page_table,
             stack: Stack::empty(),
             heap: Heap::empty(),
+            mmaps: MemoryMaps::default(),
             code: Vec::new(),
             code_usage: 0,
+            segments: Vec::new(),
         }
     }
 
@@ -73,7 +112,7 @@ impl ProcessVm {
         self
     }
 
-    pub fn init_proc_stack(&mut self, pid: ProcessId) -> VirtAddr {
+    pub fn init_proc_stack(&mut self, pid: ProcessId) -> Result<VirtAddr, MapToError<Size4KiB>> {
         // debug!("STACK_MAX_SIZE: {:#x}", STACK_MAX_SIZE);
         // FIXME: calculate the stack for pid
         debug!("PID: {:#x}", pid.0);
@@ -87,40 +126,80 @@ impl ProcessVm {
             &mut self.page_table.mapper(),
             frame_allocator,
             true,
-            false,
-        );
+            true,
+        )?;
 
         self.stack = Stack::new(
             Page::containing_address(VirtAddr::new(stack_top_addr)),
             STACK_DEF_PAGE,
         );
 
-        VirtAddr::new(stack_top_addr)
+        Ok(VirtAddr::new(stack_top_addr))
     }
 
+    /// The top of the user stack, its first page is mapped by `load_elf`
     pub fn init_user_proc_stack(&mut self, pid: ProcessId) -> VirtAddr {
-        // debug!("STACK_MAX_SIZE: {:#x}", STACK_MAX_SIZE);
-        // FIXME: calculate the stack for pid
+        // every user process has its own address space, the stack is at the same place
         debug!("PID: {:#x}", pid.0);
-        let stack_bot_addr = STACK_INIT_BOT;
-        let stack_top_addr = STACK_INIT_TOP;
-        let frame_allocator = &mut *get_frame_alloc_for_sure();
+        VirtAddr::new(STACK_INIT_TOP)
+    }
 
-        map_pages(
-            stack_top_addr,
-            STACK_DEF_PAGE,
-            &mut self.page_table.mapper(),
-            frame_allocator,
-            true,
-            false,
-        );
+    /// Copy `argv` and `envp` to the user stack below `stack_top`
+    ///
+    /// The strings are stored at the top of the stack, `argc` and the
+    /// null-terminated `argv` and `envp` arrays right below them. The whole
+    /// image must fit in the initial stack page, see `ARG_MAX`.
+    pub fn init_user_args(
+        &mut self,
+        stack_top: VirtAddr,
+        argv: &[String],
+        envp: &[String],
+    ) -> UserArgs {
+        let top = stack_top.as_u64();
+        let strs_size: u64 = argv.iter().chain(envp).map(|s| s.len() as u64 + 1).sum();
+        let ptrs_size = (argv.len() + envp.len() + 3) as u64 * 8;
+
+        // the entry point sees `rsp + 8` aligned to 16 bytes, like after a call
+        let base = (top - strs_size - ptrs_size) & !0xf;
+        let mut image = vec![0u8; (top - base) as usize];
+
+        let mut ptrs = Vec::with_capacity(argv.len() + envp.len() + 3);
+        let mut str_addr = base + ptrs_size;
+        ptrs.push(argv.len() as u64);
+        for strs in [argv, envp] {
+            for s in strs {
+                let offset = (str_addr - base) as usize;
+                image[offset..offset + s.len()].copy_from_slice(s.as_bytes());
+                ptrs.push(str_addr);
+                str_addr += s.len() as u64 + 1;
+            }
+            ptrs.push(0);
+        }
 
-        self.stack = Stack::new(
-            Page::containing_address(VirtAddr::new(stack_top_addr)),
-            STACK_DEF_PAGE,
-        );
+        for (i, ptr) in ptrs.iter().enumerate() {
+            image[i * 8..(i + 1) * 8].copy_from_slice(&ptr.to_ne_bytes());
+        }
+
+        // the page table may not be loaded, write through the physical mapping
+        let phys = self
+            .page_table
+            .mapper()
+            .translate_addr(VirtAddr::new(base))
+            .expect("User stack is not mapped");
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                image.as_ptr(),
+                physical_to_virtual(phys.as_u64()) as *mut u8,
+                image.len(),
+            );
+        }
 
-        VirtAddr::new(stack_top_addr)
+        UserArgs {
+            stack_top: VirtAddr::new(base - 8),
+            argc: argv.len(),
+            argv: VirtAddr::new(base + 8),
+            envp: VirtAddr::new(base + 8 * (argv.len() as u64 + 2)),
+        }
     }
 
     // pub fn clean_user_stack(&mut self) {
@@ -167,55 +246,334 @@ impl ProcessVm {
         )
     }
 
-    pub fn load_elf(&mut self, elf: &ElfFile) {
-        let mapper = &mut self.page_table.mapper();
+    pub fn load_elf(&mut self, elf: &ElfFile<'static>) -> Result<(), MapToError<Size4KiB>> {
+        self.load_elf_code(elf);
 
+        let mapper = &mut self.page_table.mapper();
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        self.load_elf_code(elf, mapper, alloc);
-        self.stack.init(mapper, alloc);
+        self.stack.init(mapper, alloc)
+    }
+
+    /// Record the segments of `elf`, nothing is mapped until they are accessed
+    fn load_elf_code(&mut self, elf: &ElfFile<'static>) {
+        self.segments = Segment::load(elf).collect();
+        self.code = self.segments.iter().map(Segment::pages).collect();
+
+        // code usage counts the resident pages
+        self.code_usage = 0;
     }
 
-    fn load_elf_code(&mut self, elf: &ElfFile, mapper: MapperRef, alloc: FrameAllocatorRef) {
-        // FIXME: make the `load_elf` function return the code pages
-        self.code =
-            elf::load_elf(elf, *PHYSICAL_OFFSET.get().unwrap(), mapper, alloc, true).unwrap();
+    /// Fork the address space into `page_table`, a fresh copy of the kernel's
+    ///
+    /// Every user page is mapped to the same frame in both tables,
+    /// writable pages become read-only copy-on-write pages in both.
+    /// If the child runs out of frames, what it got is freed with it.
+    pub fn fork(&self, page_table: PageTableContext) -> Result<Self, MapToError<Size4KiB>> {
+        let child = Self {
+            page_table,
+            stack: self.stack.fork(),
+            heap: self.heap.fork(),
+            mmaps: self.mmaps.clone(),
+            code: self.code.clone(),
+            code_usage: self.code_usage,
+            segments: self.segments.clone(),
+        };
+
+        // on failure the child is dropped once the frame allocator is released
+        self.share_with(&child)?;
+        Ok(child)
+    }
 
-        // FIXME: calculate code usage
-        self.code_usage = self
+    /// Map the user pages of the process in the page table of `child`
+    fn share_with(&self, child: &Self) -> Result<(), MapToError<Size4KiB>> {
+        let mapper = &mut self.page_table.mapper();
+        let child_mapper = &mut child.page_table.mapper();
+        let alloc = &mut *get_frame_alloc_for_sure();
+
+        let stack = self.stack.range();
+        let ranges = self
             .code
             .iter()
-            .map(|range| range.count() as u64)
-            .sum::<u64>()
-            * PAGE_SIZE as u64;
+            .copied()
+            .chain(self.heap.range())
+            .chain(self.mmaps.ranges())
+            .chain(core::iter::once(Page::range_inclusive(
+                stack.start,
+                stack.end - 1,
+            )));
+
+        for range in ranges {
+            share_range(range, mapper, child_mapper, alloc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Back the page at `addr` if it is in the stack, heap, code or a mapped region,
+    /// returns false if it is not
+    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> Result<bool, MapToError<Size4KiB>> {
+        let mapper = &mut self.page_table.mapper();
+        let alloc = &mut *get_frame_alloc_for_sure();
+
+        Ok(self.stack.handle_page_fault(addr, mapper, alloc)?
+            || self.heap.handle_page_fault(addr, mapper, alloc)?
+            || self.load_code_page(addr, mapper, alloc)?
+            || self.mmaps.handle_page_fault(addr, mapper, alloc)?)
     }
 
-    pub fn fork(&self, stack_offset_count: u64) -> Self {
-        let owned_page_table = self.page_table.fork();
-        let mapper = &mut owned_page_table.mapper();
+    /// Load the page at `addr` if it belongs to a segment of the program
+    fn load_code_page(
+        &mut self,
+        addr: VirtAddr,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<bool, MapToError<Size4KiB>> {
+        let page = Page::containing_address(addr);
+        let Some(segment) = self.segments.iter().find(|segment| segment.contains(page)) else {
+            return Ok(false);
+        };
+
+        segment.map_page(page, mapper, alloc)?;
+        self.code_usage += PAGE_SIZE;
+        Ok(true)
+    }
 
+    /// Map `len` bytes, page aligned, at `addr` if `fixed` or somewhere free
+    ///
+    /// Pages are mapped with `flags`, or cannot be accessed if it is `None`.
+    /// The start of the region is filled with `data`, the rest with zeros.
+    pub fn mmap(
+        &mut self,
+        addr: u64,
+        len: u64,
+        fixed: bool,
+        flags: Option<PageTableFlags>,
+        data: &[u8],
+    ) -> Option<VirtAddr> {
+        let mapper = &mut self.page_table.mapper();
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        Self {
-            page_table: owned_page_table,
-            stack: self.stack.fork(mapper, alloc, stack_offset_count),
-            heap: self.heap.fork(),
+        // a fixed mapping replaces what was there
+        if fixed {
+            self.mmaps.unmap(addr, addr + len, mapper, alloc);
+        }
 
-            // do not share code info
-            code: Vec::new(),
-            code_usage: 0,
+        let start = self.mmaps.find_free(addr, len)?;
+        if fixed && start != addr {
+            return None;
+        }
+
+        match self.mmaps.map(start, len, flags, data, mapper, alloc) {
+            Ok(()) => Some(VirtAddr::new(start)),
+            Err(err) => {
+                error!("Failed to mmap {:#x} bytes: {:?}", len, err);
+                None
+            }
+        }
+    }
+
+    /// Unmap the mapped pages of `[addr, addr + len)`
+    pub fn munmap(&mut self, addr: u64, len: u64) {
+        let mapper = &mut self.page_table.mapper();
+        let alloc = &mut *get_frame_alloc_for_sure();
+
+        self.mmaps.unmap(addr, addr + len, mapper, alloc);
+    }
+
+    /// Change the flags of the pages of `[addr, addr + len)`, page aligned,
+    /// `None` makes them inaccessible to the process
+    ///
+    /// Returns false if a page is outside the code, heap, stack and mapped regions.
+    /// Pages sharing a frame with a forked process stay copy-on-write.
+    pub fn mprotect(&mut self, addr: u64, len: u64, flags: Option<PageTableFlags>) -> bool {
+        let start = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let end = Page::containing_address(VirtAddr::new(addr + len - 1));
+        let pages = Page::range_inclusive(start, end);
+
+        if !pages.into_iter().all(|page| self.contains_page(page)) {
+            return false;
+        }
+
+        // load code and heap pages now, they would get their default flags later
+        for page in pages {
+            if self.page_flags(page).is_none()
+                && self.mmaps.find(page.start_address().as_u64()).is_none()
+                && self.handle_page_fault(page.start_address()).is_err()
+            {
+                return false;
+            }
         }
+
+        // pages of mapped regions that are not backed yet get the flags on the first access
+        self.mmaps.protect(addr, addr + len, flags);
+
+        let mapper = &mut self.page_table.mapper();
+        let alloc = &*get_frame_alloc_for_sure();
+
+        for page in pages {
+            let frame = match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped {
+                    frame: MappedFrame::Size4KiB(frame),
+                    ..
+                } => frame,
+                _ => continue,
+            };
+
+            let flags = match flags {
+                // the page stays present so that its frame is still freed with it
+                None => PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+                Some(flags)
+                    if flags.contains(PageTableFlags::WRITABLE) && alloc.frame_refs(frame) > 1 =>
+                {
+                    (flags - PageTableFlags::WRITABLE) | COW_FLAG
+                }
+                Some(flags) => flags,
+            };
+
+            if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+                flush.flush();
+            }
+        }
+
+        true
+    }
+
+    /// Whether `page` is in the code, heap, stack or a mapped region
+    fn contains_page(&self, page: Page) -> bool {
+        let stack = self.stack.range();
+
+        self.code
+            .iter()
+            .copied()
+            .chain(self.heap.range())
+            .any(|range| range.start <= page && page <= range.end)
+            || (stack.start <= page && page < stack.end)
+            || self.mmaps.find(page.start_address().as_u64()).is_some()
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
+    /// Give the copy-on-write page at `addr` a frame of its own and make it writable,
+    /// the last mapping of a frame takes it over without copying
+    ///
+    /// Returns false if `addr` is not on a copy-on-write page.
+    pub fn handle_cow_fault(&mut self, addr: VirtAddr) -> Result<bool, MapToError<Size4KiB>> {
+        let page = Page::<Size4KiB>::containing_address(addr);
         let mapper = &mut self.page_table.mapper();
+
+        let (frame, flags) = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } if flags.contains(COW_FLAG) => (frame, flags),
+            _ => return Ok(false),
+        };
+
+        let flags = (flags - COW_FLAG) | PageTableFlags::WRITABLE;
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        self.stack.handle_page_fault(addr, mapper, alloc)
+        if alloc.frame_refs(frame) == 1 {
+            return match unsafe { mapper.update_flags(page, flags) } {
+                Ok(flush) => {
+                    flush.flush();
+                    Ok(true)
+                }
+                Err(_) => Ok(false),
+            };
+        }
+
+        // the page is mapped, so its entry is there and nothing fails after the copy
+        let Some(entry) = p1_entry(mapper, page) else {
+            return Ok(false);
+        };
+
+        let new_frame = alloc
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        trace!(
+            "Copy-on-write {:#x}: {:#x} -> {:#x}",
+            page.start_address().as_u64(),
+            frame.start_address().as_u64(),
+            new_frame.start_address().as_u64()
+        );
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                physical_to_virtual(frame.start_address().as_u64()) as *const u8,
+                physical_to_virtual(new_frame.start_address().as_u64()) as *mut u8,
+                PAGE_SIZE as usize,
+            );
+        }
+
+        entry.set_frame(new_frame, flags);
+        x86_64::instructions::tlb::flush(page.start_address());
+
+        // drop this mapping of the shared frame
+        unsafe { alloc.deallocate_frame(frame) };
+
+        Ok(true)
+    }
+
+    /// Check that every page of `[addr, addr + len)` is user accessible,
+    /// and also writable if `write` is set.
+    ///
+    /// Pages that are not mapped yet are resolved like a page fault would,
+    /// so user buffers on a not-yet-grown stack are accepted.
+    pub fn check_user_range(&mut self, addr: VirtAddr, len: u64, write: bool) -> bool {
+        if len == 0 {
+            return true;
+        }
+
+        let start = Page::<Size4KiB>::containing_address(addr);
+        let end = Page::<Size4KiB>::containing_address(addr + (len - 1));
+
+        for page in Page::range_inclusive(start, end) {
+            let mut flags = self.page_flags(page);
+
+            if flags.is_none() && matches!(self.handle_page_fault(page.start_address()), Ok(true)) {
+                flags = self.page_flags(page);
+            }
+
+            // the kernel writes to its own copy, like the user would
+            if write
+                && flags.is_some_and(|flags| flags.contains(COW_FLAG))
+                && matches!(self.handle_cow_fault(page.start_address()), Ok(true))
+            {
+                flags = self.page_flags(page);
+            }
+
+            match flags {
+                Some(flags)
+                    if flags.contains(PageTableFlags::USER_ACCESSIBLE)
+                        && (!write || flags.contains(PageTableFlags::WRITABLE)) => {}
+                _ => return false,
+            }
+        }
+
+        true
     }
 
-    pub(super) fn memory_usage(&self) -> u64 {
-        self.stack.memory_usage() + self.heap.memory_usage() + self.code_usage
+    fn page_flags(&self, page: Page) -> Option<PageTableFlags> {
+        match self.page_table.mapper().translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+
+    pub(super) fn memory_usage(&self) -> MemoryUsage {
+        let code: u64 = self.code.iter().map(|range| range.count() as u64).sum();
+
+        MemoryUsage {
+            resident: self.stack.memory_usage()
+                + self.heap.memory_usage()
+                + self.mmaps.memory_usage()
+                + self.code_usage,
+            reserved: self.stack.memory_usage()
+                + self.heap.reserved()
+                + self.mmaps.reserved()
+                + code * PAGE_SIZE,
+        }
     }
 
     pub(super) fn clean_up(&mut self) -> Result<(), UnmapError> {
@@ -224,15 +582,16 @@ impl ProcessVm {
 
         // FIXME: implement the `clean_up` function for `Stack`
         self.stack.clean_up(mapper, dealloc)?;
+        self.mmaps.clean_up(mapper, dealloc);
 
         if self.page_table.using_count() == 1 {
             // free heap
             // FIXME: implement the `clean_up` function for `Heap`
             self.heap.clean_up(mapper, dealloc)?;
 
-            // free code
+            // free code, pages that were never loaded are not mapped
             for page_range in self.code.iter() {
-                elf::unmap_range(*page_range, mapper, dealloc, true);
+                unmap_mapped(*page_range, mapper, dealloc);
             }
 
             unsafe {
@@ -251,14 +610,118 @@ impl ProcessVm {
     }
 }
 
+/// Map `page` to a new zeroed frame with `flags`,
+/// `data` is copied into the frame at `offset`
+/// The level 1 entry of `page`, if all the tables down to it are there
+fn p1_entry(mapper: MapperRef, page: Page) -> Option<&mut PageTableEntry> {
+    let next = |entry: &PageTableEntry| {
+        let frame = entry.frame().ok()?;
+        let table = physical_to_virtual(frame.start_address().as_u64()) as *mut PageTable;
+        Some(unsafe { &mut *table })
+    };
+
+    let p4 = mapper.level_4_table_mut();
+    let p3 = next(&p4[page.p4_index()])?;
+    let p2 = next(&p3[page.p3_index()])?;
+    let p1 = next(&p2[page.p2_index()])?;
+    Some(&mut p1[page.p1_index()])
+}
+
+fn map_zeroed_page(
+    page: Page,
+    flags: PageTableFlags,
+    offset: usize,
+    data: &[u8],
+    mapper: MapperRef,
+    alloc: FrameAllocatorRef,
+) -> Result<(), MapToError<Size4KiB>> {
+    debug_assert!(offset + data.len() <= PAGE_SIZE as usize);
+
+    let frame = alloc
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    unsafe {
+        let dest = physical_to_virtual(frame.start_address().as_u64()) as *mut u8;
+        core::ptr::write_bytes(dest, 0, PAGE_SIZE as usize);
+        core::ptr::copy_nonoverlapping(data.as_ptr(), dest.add(offset), data.len());
+
+        match mapper.map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, alloc) {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                alloc.deallocate_frame(frame);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmap the pages of `range` that are mapped and free their frames,
+/// returns the number of pages unmapped
+fn unmap_mapped(range: PageRangeInclusive, mapper: MapperRef, dealloc: FrameAllocatorRef) -> u64 {
+    let mut count = 0;
+
+    for page in range {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            unsafe { dealloc.deallocate_frame(frame) };
+            flush.flush();
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Map the pages of `range` that are mapped in `mapper` to the same frames
+/// in `child`, writable pages are made copy-on-write in both
+fn share_range(
+    range: PageRangeInclusive,
+    mapper: MapperRef,
+    child: MapperRef,
+    alloc: FrameAllocatorRef,
+) -> Result<(), MapToError<Size4KiB>> {
+    for page in range {
+        let (frame, mut flags) = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => (frame, flags),
+            _ => continue,
+        };
+
+        if flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags - PageTableFlags::WRITABLE) | COW_FLAG;
+            if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+                flush.flush();
+            }
+        }
+
+        unsafe {
+            child
+                .map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, alloc)?
+                .ignore();
+        }
+        alloc.share_frame(frame);
+    }
+
+    Ok(())
+}
+
 impl core::fmt::Debug for ProcessVm {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        let (size, unit) = humanized_size(self.memory_usage());
+        let usage = self.memory_usage();
+        let (resident, resident_unit) = humanized_size(usage.resident);
+        let (reserved, reserved_unit) = humanized_size(usage.reserved);
 
         f.debug_struct("ProcessVm")
             .field("stack", &self.stack)
             .field("heap", &self.heap)
-            .field("memory_usage", &format!("{} {}", size, unit))
+            .field("mmaps", &self.mmaps)
+            .field("resident", &format!("{} {}", resident, resident_unit))
+            .field("reserved", &format!("{} {}", reserved, reserved_unit))
             .field("page_table", &self.page_table)
             .finish()
     }
@@ -0,0 +1,108 @@
+//! Loadable segments of a user program
+//!
+//! Loading a program only records its segments, every page is filled from
+//! the ELF image on its first access.
+
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        Page, PageTableFlags, Size4KiB, mapper::MapToError, page::PageRangeInclusive,
+    },
+};
+use xmas_elf::{ElfFile, program};
+
+use super::{FrameAllocatorRef, MapperRef, map_zeroed_page};
+use crate::memory::PAGE_SIZE;
+
+/// A `PT_LOAD` segment backed by the in-memory ELF image
+#[derive(Clone)]
+pub struct Segment {
+    /// address of the first byte of the segment
+    start: u64,
+    /// size of the segment in memory, bytes past `data` are zeros
+    mem_size: u64,
+    /// the bytes of the segment in the image
+    data: &'static [u8],
+    flags: PageTableFlags,
+}
+
+impl Segment {
+    /// The loadable segments of `elf`, see `check_segments` for the image layout
+    pub fn load(elf: &ElfFile<'static>) -> impl Iterator<Item = Segment> {
+        let input: &'static [u8] = elf.input;
+
+        elf.program_iter()
+            .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+            .filter(|segment| segment.mem_size() > 0)
+            .map(move |segment| {
+                let offset = segment.offset() as usize;
+                Segment {
+                    start: segment.virtual_addr(),
+                    mem_size: segment.mem_size(),
+                    data: &input[offset..offset + segment.file_size() as usize],
+                    flags: elf::segment_flags(&segment, true),
+                }
+            })
+    }
+
+    /// The pages covered by the segment
+    pub fn pages(&self) -> PageRangeInclusive {
+        Page::range_inclusive(
+            Page::containing_address(VirtAddr::new(self.start)),
+            Page::containing_address(VirtAddr::new(self.start + self.mem_size - 1)),
+        )
+    }
+
+    pub fn contains(&self, page: Page) -> bool {
+        let pages = self.pages();
+        pages.start <= page && page <= pages.end
+    }
+
+    /// Fill `page` of the segment with its part of the image
+    pub fn map_page(
+        &self,
+        page: Page,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let page_start = page.start_address().as_u64();
+
+        // the part of the file data that lies in the page
+        let from = self.start.max(page_start);
+        let to = (self.start + self.data.len() as u64).min(page_start + PAGE_SIZE);
+        let data = if from < to {
+            &self.data[(from - self.start) as usize..(to - self.start) as usize]
+        } else {
+            &[]
+        };
+
+        map_zeroed_page(
+            page,
+            self.flags,
+            (from - page_start) as usize,
+            data,
+            mapper,
+            alloc,
+        )
+    }
+}
+
+/// Check that the loadable segments of `elf` can be loaded by `Segment::load`
+///
+/// Their data must lie in the image, inside the user half of the address space.
+pub fn check_segments(elf: &ElfFile) -> bool {
+    elf.program_iter()
+        .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+        .all(|segment| {
+            let in_image = segment
+                .offset()
+                .checked_add(segment.file_size())
+                .is_some_and(|end| end <= elf.input.len() as u64);
+            let in_user = segment
+                .virtual_addr()
+                .checked_add(segment.mem_size())
+                .is_some_and(|end| end <= crate::proc::uaccess::USER_SPACE_END);
+
+            in_image && in_user && segment.file_size() <= segment.mem_size()
+        })
+}
@@ -3,10 +3,10 @@ use x86_64::{
     structures::paging::{Page, mapper::MapToError, page::*},
 };
 
-use super::{FrameAllocatorRef, MapperRef};
+use super::{FrameAllocatorRef, MapperRef, unmap_mapped};
+use crate::memory::get_frame_alloc_for_sure;
 use crate::proc;
-use crate::proc::processor;
-use core::ptr::copy_nonoverlapping;
+use crate::proc::{PageTableContext, ProcessId, processor};
 use elf::*;
 use x86_64::structures::paging::mapper::UnmapError;
 
@@ -36,6 +36,15 @@ pub const KSTACK_DEF_SIZE: u64 = KSTACK_DEF_PAGE * crate::memory::PAGE_SIZE;
 pub const KSTACK_INIT_BOT: u64 = KSTACK_MAX - KSTACK_DEF_SIZE;
 pub const KSTACK_INIT_TOP: u64 = KSTACK_MAX - 8;
 
+// [0xffffff0100000000..0xffffff017fffffff]
+// kernel stacks of the user processes, one slot per pid, the pages below
+// the stack in each slot are left unmapped to catch overflows
+pub const KSTACK_PROC_BOT: u64 = KSTACK_DEF_BOT;
+pub const KSTACK_PROC_SLOT: u64 = 0x8000;
+pub const KSTACK_PROC_END: u64 = KSTACK_PROC_BOT + KSTACK_PROC_SLOT * 0x10000;
+pub const KSTACK_PROC_PAGES: u64 = 4;
+pub const KSTACK_PROC_SIZE: u64 = KSTACK_PROC_PAGES * crate::memory::PAGE_SIZE;
+
 const KSTACK_INIT_PAGE: Page<Size4KiB> = Page::containing_address(VirtAddr::new(KSTACK_INIT_BOT));
 const KSTACK_INIT_TOP_PAGE: Page<Size4KiB> =
     Page::containing_address(VirtAddr::new(KSTACK_INIT_TOP));
@@ -67,30 +76,33 @@ impl Stack {
         }
     }
 
-    pub fn init(&mut self, mapper: MapperRef, alloc: FrameAllocatorRef) {
+    pub fn init(
+        &mut self,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<(), MapToError<Size4KiB>> {
         debug_assert!(self.usage == 0, "Stack is not empty.");
 
-        self.range =
-            elf::map_pages(STACK_INIT_BOT, STACK_DEF_PAGE, mapper, alloc, true, false).unwrap();
+        self.range = elf::map_pages(STACK_INIT_BOT, STACK_DEF_PAGE, mapper, alloc, true, true)?;
         self.usage = STACK_DEF_PAGE;
+
+        Ok(())
     }
 
+    /// Grow the stack down to `addr` if it is on the stack,
+    /// returns false if it is not
     pub fn handle_page_fault(
         &mut self,
         addr: VirtAddr,
         mapper: MapperRef,
         alloc: FrameAllocatorRef,
-    ) -> bool {
+    ) -> Result<bool, MapToError<Size4KiB>> {
         if !self.is_on_stack(addr) {
-            return false;
-        }
-
-        if let Err(m) = self.grow_stack(addr, mapper, alloc) {
-            error!("Grow stack failed: {:?}", m);
-            return false;
+            return Ok(false);
         }
 
-        true
+        self.grow_stack(addr, mapper, alloc)?;
+        Ok(true)
     }
 
     fn is_on_stack(&self, addr: VirtAddr) -> bool {
@@ -98,7 +110,9 @@ impl Stack {
         let cur_stack_bot = self.range.start.start_address().as_u64();
         trace!("Current stack bot: {:#x}", cur_stack_bot);
         trace!("Address to access: {:#x}", addr);
+        // the kernel stacks of the user processes are not part of the boot stack
         addr & STACK_START_MASK == cur_stack_bot & STACK_START_MASK
+            && !(KSTACK_PROC_BOT..KSTACK_PROC_END).contains(&addr)
     }
 
     fn grow_stack(
@@ -133,14 +147,25 @@ impl Stack {
                 info!("Growing kernel stack from {:#x} to {:#x}", cur_stack_bot, new_stack_bot);
             }
 
-            elf::map_pages(
+            let mapped = elf::map_pages(
                 new_stack_bot,
                 needed_pages,
                 mapper,
                 alloc,
                 user_access,
-                false,
-            )?;
+                true,
+            );
+
+            // give back the pages mapped before running out of frames
+            if let Err(err) = mapped {
+                let start = Page::containing_address(VirtAddr::new(new_stack_bot));
+                unmap_mapped(
+                    Page::range_inclusive(start, self.range.start - 1),
+                    mapper,
+                    alloc,
+                );
+                return Err(err);
+            }
 
             self.range.start = Page::containing_address(VirtAddr::new(new_stack_bot));
             self.usage += needed_pages;
@@ -157,46 +182,14 @@ impl Stack {
         &self.range
     }
 
-    pub fn fork(
-        &self,
-        mapper: MapperRef,
-        alloc: FrameAllocatorRef,
-        mut stack_offset_count: u64,
-    ) -> Self {
-        let mut child_stack_top =
-            self.range.start.start_address().as_u64() - stack_offset_count * STACK_MAX_SIZE;
-        while elf::map_pages(child_stack_top, self.usage, mapper, alloc, true, false).is_err() {
-            trace!(
-                "Failed to map new stack on {:#x}, retrying...",
-                child_stack_top
-            );
-            child_stack_top -= STACK_MAX_SIZE;
-        }
-
-        self.clone_range(
-            self.range.start.start_address().as_u64(),
-            child_stack_top,
-            self.usage,
-        );
-
-        let start = Page::containing_address(VirtAddr::new(child_stack_top));
+    /// The stack of a forked process, at the same address
+    pub fn fork(&self) -> Self {
         Self {
-            range: Page::range(start, start + self.usage),
+            range: self.range,
             usage: self.usage,
         }
     }
 
-    fn clone_range(&self, cur_addr: u64, dest_addr: u64, size: u64) {
-        trace!("Clone range: {:#x} -> {:#x}", cur_addr, dest_addr);
-        unsafe {
-            copy_nonoverlapping::<u64>(
-                cur_addr as *mut u64,
-                dest_addr as *mut u64,
-                (size * Size4KiB::SIZE / 8) as usize,
-            );
-        }
-    }
-
     pub fn clean_up(
         &mut self,
         // following types are defined in
@@ -209,9 +202,12 @@ impl Stack {
             return Ok(());
         }
 
-        // FIXME: unmap stack pages with `elf::unmap_pagess`
-        let stack_top = self.range.start.start_address().as_u64();
-        elf::unmap_pages(stack_top, self.usage, mapper, dealloc);
+        // the stack of a process that failed to fork may be partly mapped
+        unmap_mapped(
+            Page::range_inclusive(self.range.start, self.range.end - 1),
+            mapper,
+            dealloc,
+        );
         self.usage = 0;
 
         Ok(())
@@ -232,3 +228,65 @@ impl core::fmt::Debug for Stack {
             .finish()
     }
 }
+
+/// The stack a user process runs on in kernel mode
+///
+/// Interrupts and syscalls from user mode land on it through
+/// `TSS.privilege_stack_table[0]`, so a process can block in the middle of a
+/// syscall and resume later. It lives in the slot of the pid in the kernel
+/// address space shared by all page tables, and is freed with the process.
+pub struct KernelStack {
+    range: PageRange<Size4KiB>,
+}
+
+impl KernelStack {
+    pub fn new(pid: ProcessId) -> Result<Self, MapToError<Size4KiB>> {
+        let top = KSTACK_PROC_BOT + (pid.0 as u64 + 1) * KSTACK_PROC_SLOT;
+        let bot = top - KSTACK_PROC_SIZE;
+
+        let page_table = PageTableContext::new();
+        let mapper = &mut page_table.mapper();
+        let alloc = &mut *get_frame_alloc_for_sure();
+
+        match elf::map_pages(bot, KSTACK_PROC_PAGES, mapper, alloc, false, true) {
+            Ok(range) => Ok(Self { range }),
+            Err(err) => {
+                // give back the pages mapped before running out of frames
+                let start = Page::containing_address(VirtAddr::new(bot));
+                unmap_mapped(
+                    Page::range_inclusive(start, start + KSTACK_PROC_PAGES - 1),
+                    mapper,
+                    alloc,
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// The address the stack grows down from
+    pub fn top(&self) -> VirtAddr {
+        self.range.end.start_address()
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let page_table = PageTableContext::new();
+        let mapper = &mut page_table.mapper();
+        let dealloc = &mut *get_frame_alloc_for_sure();
+
+        unmap_mapped(
+            Page::range_inclusive(self.range.start, self.range.end - 1),
+            mapper,
+            dealloc,
+        );
+    }
+}
+
+impl core::fmt::Debug for KernelStack {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("KernelStack")
+            .field("top", &format_args!("{:#x}", self.top().as_u64()))
+            .finish()
+    }
+}
@@ -0,0 +1,146 @@
+//! Kernel clocksource
+//!
+//! The TSC is calibrated against the PIT channel 2 at boot and used as the
+//! monotonic clock. Without a usable TSC, time advances with the APIC timer
+//! ticks at their nominal length instead.
+//!
+//! The realtime clock is the wall-clock time read from the UEFI runtime
+//! once at boot, advanced by the monotonic clock.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86::cpuid::CpuId;
+use x86_64::instructions::port::Port;
+
+use crate::interrupt::clock::{current_time_fixed, read_counter};
+
+/// Nominal length of a timer tick in nanoseconds
+///
+/// The APIC timer counts down 0x20000 bus cycles with divide 1,
+/// and QEMU runs the APIC bus at 1 GHz.
+pub const TICK_NS: u64 = 0x20000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Input clock of the PIT
+const PIT_FREQUENCY: u64 = 1_193_182;
+/// Length of the calibration window
+const CALIBRATE_MS: u64 = 10;
+/// Give up if the PIT has not fired after this many polls
+const CALIBRATE_MAX_POLLS: u64 = 100_000_000;
+
+/// TSC frequency in Hz, 0 if the TSC is not used
+static TSC_FREQ: AtomicU64 = AtomicU64::new(0);
+/// TSC value at boot
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Wall-clock time at boot, in nanoseconds since the Unix epoch
+static BOOT_REALTIME_NS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    if let Some(dt) = current_time_fixed() {
+        let ns = dt.timestamp_nanos_opt().unwrap_or(0).max(0) as u64;
+        BOOT_REALTIME_NS.store(ns, Ordering::Relaxed);
+    } else {
+        warn!("Failed to read the wall-clock time, realtime starts at epoch.");
+    }
+
+    let has_tsc = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_tsc());
+
+    let freq = if has_tsc { tsc_frequency() } else { None };
+
+    match freq {
+        Some(freq) => {
+            TSC_BASE.store(rdtsc(), Ordering::Relaxed);
+            TSC_FREQ.store(freq, Ordering::Relaxed);
+            info!(
+                "Clocksource: TSC @ {}.{:03} MHz",
+                freq / 1_000_000,
+                freq / 1_000 % 1_000
+            );
+        }
+        None => {
+            warn!("Clocksource: no usable TSC, falling back to timer ticks.");
+        }
+    }
+}
+
+/// Query the TSC frequency from CPUID, or measure it with the PIT
+fn tsc_frequency() -> Option<u64> {
+    let invariant = CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .is_some_and(|info| info.has_invariant_tsc());
+    if !invariant {
+        warn!("TSC is not invariant, time may drift with frequency scaling.");
+    }
+
+    CpuId::new()
+        .get_tsc_info()
+        .and_then(|info| info.tsc_frequency())
+        .or_else(calibrate_tsc)
+}
+
+/// Count TSC cycles while the PIT channel 2 counts down `CALIBRATE_MS`
+fn calibrate_tsc() -> Option<u64> {
+    let latch = PIT_FREQUENCY * CALIBRATE_MS / 1000;
+
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+
+    let (start, end) = unsafe {
+        // enable the channel 2 gate, keep the speaker off
+        let saved = gate.read();
+        gate.write((saved & !0x02) | 0x01);
+
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        command.write(0b1011_0000);
+        channel2.write(latch as u8);
+        channel2.write((latch >> 8) as u8);
+
+        let start = rdtsc();
+        let mut polls = 0;
+        // OUT2 goes high when the count reaches zero
+        while gate.read() & 0x20 == 0 {
+            polls += 1;
+            if polls > CALIBRATE_MAX_POLLS {
+                gate.write(saved);
+                return None;
+            }
+        }
+        let end = rdtsc();
+
+        gate.write(saved);
+        (start, end)
+    };
+
+    let freq = (end - start) * 1000 / CALIBRATE_MS;
+    (freq > 0).then_some(freq)
+}
+
+#[inline]
+fn rdtsc() -> u64 {
+    unsafe { x86::time::rdtsc() }
+}
+
+/// Nanoseconds since boot, never goes backwards
+pub fn monotonic_ns() -> u64 {
+    let freq = TSC_FREQ.load(Ordering::Relaxed);
+    if freq == 0 {
+        return read_counter() * TICK_NS;
+    }
+
+    let cycles = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+    (cycles as u128 * NANOS_PER_SEC as u128 / freq as u128) as u64
+}
+
+/// Nanoseconds since the Unix epoch
+pub fn realtime_ns() -> u64 {
+    BOOT_REALTIME_NS.load(Ordering::Relaxed) + monotonic_ns()
+}
+
+/// Time elapsed since boot
+pub fn uptime() -> Duration {
+    Duration::from_nanos(monotonic_ns())
+}
@@ -3,11 +3,12 @@ pub mod macros;
 #[macro_use]
 pub mod regs;
 
-// pub mod clock;
+pub mod clock;
 // use crate::interrupt::clock::*;
 
 pub mod func;
 pub mod logger;
+pub mod pipe;
 pub mod resource;
 
 pub use macros::*;
@@ -0,0 +1,138 @@
+//! Anonymous pipes
+//!
+//! A pipe is a bounded ring buffer shared by its read and write ends.
+//! Readers block while it is empty and writers while it is full, they wait
+//! on the pipe and issue their syscall again once the other side makes progress.
+
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use spin::Mutex;
+use syscall_def::Errno;
+
+use crate::proc::{ProcessId, manager::get_process_manager};
+
+/// Capacity of a pipe in bytes
+pub const PIPE_SIZE: usize = 0x1000;
+
+#[derive(Debug)]
+struct PipeBuffer {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    read_waiters: BTreeSet<ProcessId>,
+    write_waiters: BTreeSet<ProcessId>,
+}
+
+impl PipeBuffer {
+    fn wake_up(waiters: &mut BTreeSet<ProcessId>) {
+        let manager = get_process_manager();
+        for pid in core::mem::take(waiters) {
+            manager.wake_up(pid, None);
+        }
+    }
+}
+
+/// Create a pipe, returns its read and write ends
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Mutex::new(PipeBuffer {
+        buf: VecDeque::with_capacity(PIPE_SIZE),
+        readers: 1,
+        writers: 1,
+        read_waiters: BTreeSet::new(),
+        write_waiters: BTreeSet::new(),
+    }));
+
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+#[derive(Debug)]
+pub struct PipeReader(Arc<Mutex<PipeBuffer>>);
+
+#[derive(Debug)]
+pub struct PipeWriter(Arc<Mutex<PipeBuffer>>);
+
+impl PipeReader {
+    /// Read what is available, 0 at end of file
+    ///
+    /// Returns `WouldBlock` if the pipe is empty and still has writers.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pipe = self.0.lock();
+        if pipe.buf.is_empty() {
+            if pipe.writers == 0 {
+                return Ok(0);
+            }
+            return Err(Errno::WouldBlock);
+        }
+
+        let len = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+
+        PipeBuffer::wake_up(&mut pipe.write_waiters);
+        Ok(len)
+    }
+
+    /// Wake up `pid` once there is data or no writer is left
+    pub fn add_waiter(&self, pid: ProcessId) {
+        self.0.lock().read_waiters.insert(pid);
+    }
+}
+
+impl PipeWriter {
+    /// Write as much as fits into the pipe
+    ///
+    /// Returns `BrokenPipe` if there are no readers left,
+    /// and `WouldBlock` if the pipe is full.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pipe = self.0.lock();
+        if pipe.readers == 0 {
+            return Err(Errno::BrokenPipe);
+        }
+
+        let len = buf.len().min(PIPE_SIZE - pipe.buf.len());
+        if len == 0 {
+            return Err(Errno::WouldBlock);
+        }
+
+        pipe.buf.extend(&buf[..len]);
+
+        PipeBuffer::wake_up(&mut pipe.read_waiters);
+        Ok(len)
+    }
+
+    /// Wake up `pid` once there is space or no reader is left
+    pub fn add_waiter(&self, pid: ProcessId) {
+        self.0.lock().write_waiters.insert(pid);
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut pipe = self.0.lock();
+        pipe.readers -= 1;
+        if pipe.readers == 0 {
+            // writers find out that the pipe is broken
+            PipeBuffer::wake_up(&mut pipe.write_waiters);
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut pipe = self.0.lock();
+        pipe.writers -= 1;
+        if pipe.writers == 0 {
+            // readers see the end of file
+            PipeBuffer::wake_up(&mut pipe.read_waiters);
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use alloc::collections::BTreeMap;
 use alloc::string::String;
-use spin::{Mutex, RwLock};
-use storage::FileHandle;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use storage::{FileHandle, SeekFrom};
+use syscall_def::Errno;
 
+use crate::drivers::filesystem::fs_errno;
 use crate::input::try_pop_key;
+use crate::proc::ProcessId;
+use crate::utils::pipe::{PipeReader, PipeWriter};
 
 #[derive(Debug, Clone)]
 pub enum StdIO {
@@ -12,9 +18,18 @@ pub enum StdIO {
     Stderr,
 }
 
+/// An entry in the fd table
+#[derive(Debug, Clone)]
+pub struct FileDescriptor {
+    /// open resources are shared with forked processes and duplicated fds
+    pub res: Arc<Mutex<Resource>>,
+    /// closed when the process calls `Exec`
+    pub cloexec: bool,
+}
+
 #[derive(Debug)]
 pub struct ResourceSet {
-    pub handles: BTreeMap<u8, Mutex<Resource>>,
+    pub handles: BTreeMap<u8, FileDescriptor>,
 }
 
 impl Default for ResourceSet {
@@ -23,88 +38,221 @@ impl Default for ResourceSet {
             handles: BTreeMap::new(),
         };
 
-        res.open(Resource::Console(StdIO::Stdin));
-        res.open(Resource::Console(StdIO::Stdout));
-        res.open(Resource::Console(StdIO::Stderr));
+        // the table is empty, these cannot fail
+        let _ = res.open(Resource::Console(StdIO::Stdin), false);
+        let _ = res.open(Resource::Console(StdIO::Stdout), false);
+        let _ = res.open(Resource::Console(StdIO::Stderr), false);
 
         res
     }
 }
 
 impl ResourceSet {
-    pub fn open(&mut self, res: Resource) -> u8 {
-        let fd = self.handles.len() as u8;
-        self.handles.insert(fd, Mutex::new(res));
-        fd
+    /// The lowest fd that is not in use
+    fn free_fd(&self) -> Result<u8, Errno> {
+        (0..=u8::MAX)
+            .find(|fd| !self.handles.contains_key(fd))
+            .ok_or(Errno::TooManyFiles)
     }
 
-    pub fn close(&mut self, fd: u8) -> bool {
-        self.handles.remove(&fd).is_some()
+    fn insert(&mut self, res: Arc<Mutex<Resource>>, cloexec: bool) -> Result<u8, Errno> {
+        let fd = self.free_fd()?;
+        self.handles.insert(fd, FileDescriptor { res, cloexec });
+        Ok(fd)
     }
 
-    pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
-        if let Some(count) = self.handles.get(&fd).and_then(|h| h.lock().read(buf)) {
-            count as isize
-        } else {
-            -1
+    pub fn open(&mut self, res: Resource, cloexec: bool) -> Result<u8, Errno> {
+        self.insert(Arc::new(Mutex::new(res)), cloexec)
+    }
+
+    /// A copy of the table for a forked process, sharing every resource
+    pub fn fork(&self) -> Self {
+        Self {
+            handles: self.handles.clone(),
         }
     }
 
-    pub fn write(&self, fd: u8, buf: &[u8]) -> isize {
-        if let Some(count) = self.handles.get(&fd).and_then(|h| h.lock().write(buf)) {
-            count as isize
-        } else {
-            -1
+    /// Returns the removed resource, so that the caller decides where it is dropped
+    pub fn close(&mut self, fd: u8) -> Result<Arc<Mutex<Resource>>, Errno> {
+        self.handles.remove(&fd).map(|d| d.res).ok_or(Errno::BadFd)
+    }
+
+    /// Duplicate `fd` onto the lowest free fd
+    pub fn dup(&mut self, fd: u8, cloexec: bool) -> Result<u8, Errno> {
+        let res = self.get(fd)?.clone();
+        self.insert(res, cloexec)
+    }
+
+    /// Make `new` refer to the resource of `old`, closing what `new` referred to
+    ///
+    /// Returns the replaced resource, so that the caller decides where it is dropped.
+    pub fn dup2(
+        &mut self,
+        old: u8,
+        new: u8,
+        cloexec: bool,
+    ) -> Result<Option<Arc<Mutex<Resource>>>, Errno> {
+        let res = self.get(old)?.clone();
+        if old == new {
+            return Ok(None);
         }
+
+        Ok(self
+            .handles
+            .insert(new, FileDescriptor { res, cloexec })
+            .map(|d| d.res))
+    }
+
+    /// Remove every fd marked close-on-exec, returns the removed resources
+    pub fn close_on_exec(&mut self) -> Vec<Arc<Mutex<Resource>>> {
+        let fds: Vec<u8> = self
+            .handles
+            .iter()
+            .filter(|(_, d)| d.cloexec)
+            .map(|(&fd, _)| fd)
+            .collect();
+
+        fds.into_iter()
+            .filter_map(|fd| self.handles.remove(&fd))
+            .map(|d| d.res)
+            .collect()
+    }
+
+    fn get(&self, fd: u8) -> Result<&Arc<Mutex<Resource>>, Errno> {
+        self.handles.get(&fd).map(|d| &d.res).ok_or(Errno::BadFd)
+    }
+
+    pub fn read(&self, fd: u8, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.get(fd)?.lock().read(buf)
+    }
+
+    pub fn write(&self, fd: u8, buf: &[u8]) -> Result<usize, Errno> {
+        self.get(fd)?.lock().write(buf)
+    }
+
+    pub fn seek(&self, fd: u8, pos: SeekFrom) -> Result<usize, Errno> {
+        self.get(fd)?.lock().seek(pos)
+    }
+
+    pub fn read_at(&self, fd: u8, offset: usize, len: usize) -> Result<Vec<u8>, Errno> {
+        self.get(fd)?.lock().read_at(offset, len)
+    }
+
+    pub fn add_waiter(&self, fd: u8, pid: ProcessId) -> Result<(), Errno> {
+        self.get(fd)?.lock().add_waiter(pid);
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub enum Resource {
     Console(StdIO),
-    // File(FileHandle),
+    File(FileHandle),
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
     Null,
 }
 
 impl Resource {
-    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
         match self {
             Resource::Console(stdio) => match stdio {
                 StdIO::Stdin => {
                     // FIXME: just read from kernel input buffer
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
                     if let Some(c) = try_pop_key() {
                         buf[0] = c;
-                        Some(1)
+                        Ok(1)
                     } else {
-                        Some(0)
+                        Ok(0)
                     }
                 }
-                _ => None,
+                _ => Err(Errno::BadFd),
             },
-            // Resource::File(file) => {
-
-            // }
-            Resource::Null => Some(0),
+            Resource::File(file) => file.read(buf).map_err(fs_errno),
+            Resource::PipeReader(pipe) => pipe.read(buf),
+            Resource::PipeWriter(_) => Err(Errno::BadFd),
+            Resource::Null => Ok(0),
         }
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, Errno> {
         match self {
             Resource::Console(stdio) => match *stdio {
-                StdIO::Stdin => None,
+                StdIO::Stdin => Err(Errno::BadFd),
                 StdIO::Stdout => {
                     print!("{}", String::from_utf8_lossy(buf));
-                    Some(buf.len())
+                    Ok(buf.len())
                 }
                 StdIO::Stderr => {
                     warn!("{}", String::from_utf8_lossy(buf));
-                    Some(buf.len())
+                    Ok(buf.len())
                 }
             },
-            // Resource::File(file) => {
-            //     todo!()
-            // }
-            Resource::Null => Some(buf.len()),
+            Resource::File(file) => {
+                let written = file.write(buf).map_err(fs_errno)?;
+                // keep the length seen by `read_at` up to date
+                let pos = file.seek(SeekFrom::Current(0)).map_err(fs_errno)?;
+                file.meta.len = file.meta.len.max(pos);
+                Ok(written)
+            }
+            Resource::PipeReader(_) => Err(Errno::BadFd),
+            Resource::PipeWriter(pipe) => pipe.write(buf),
+            Resource::Null => Ok(buf.len()),
+        }
+    }
+
+    /// Wake up `pid` once a read or write that would block can make progress
+    pub fn add_waiter(&self, pid: ProcessId) {
+        match self {
+            Resource::PipeReader(pipe) => pipe.add_waiter(pid),
+            Resource::PipeWriter(pipe) => pipe.add_waiter(pid),
+            _ => {}
+        }
+    }
+
+    /// Read up to `len` bytes of a file at `offset`, keeping the file position
+    pub fn read_at(&mut self, offset: usize, len: usize) -> Result<Vec<u8>, Errno> {
+        let Resource::File(file) = self else {
+            return Err(Errno::NoDevice);
+        };
+
+        let len = len.min(file.meta.len.saturating_sub(offset));
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let pos = file.seek(SeekFrom::Current(0)).map_err(fs_errno)?;
+        file.seek(SeekFrom::Start(offset)).map_err(fs_errno)?;
+
+        // the whole range goes through the kernel heap, fail instead of panicking
+        let mut buf = Vec::new();
+        buf.try_reserve_exact(len).map_err(|_| Errno::NoMemory)?;
+        buf.resize(len, 0);
+
+        let mut read = 0;
+        let ret = loop {
+            if read == len {
+                break Ok(());
+            }
+            match file.read(&mut buf[read..]) {
+                Ok(0) => break Ok(()),
+                Ok(n) => read += n,
+                Err(err) => break Err(fs_errno(err)),
+            }
+        };
+        buf.truncate(read);
+
+        file.seek(SeekFrom::Start(pos)).map_err(fs_errno)?;
+        ret.map(|_| buf)
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<usize, Errno> {
+        match self {
+            Resource::File(file) => file.seek(pos).map_err(fs_errno),
+            _ => Err(Errno::InvalidSeek),
         }
     }
 }
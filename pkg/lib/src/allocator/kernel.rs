@@ -1,16 +1,77 @@
-pub struct KernelAllocator;
+//! Heap in the brk area of the process, grown on demand
+//!
+//! The memory is private to the process and is given back by the kernel
+//! with the rest of its address space when it exits.
 
-unsafe impl alloc::alloc::GlobalAlloc for KernelAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        crate::sys_allocate(&layout)
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{NonNull, null_mut};
+use linked_list_allocator::{Heap, LockedHeap};
+
+use crate::*;
+
+/// The heap grows by at least this many bytes at a time
+const GROW_SIZE: usize = 64 * 1024; // 64 KiB
+
+pub struct KernelAllocator {
+    heap: LockedHeap,
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+
+        let ptr = match heap.allocate_first_fit(layout) {
+            Ok(ptr) => Ok(ptr),
+            Err(()) if grow(&mut heap, layout.size() + layout.align()) => {
+                heap.allocate_first_fit(layout)
+            }
+            Err(()) => Err(()),
+        };
+
+        ptr.map_or(null_mut(), NonNull::as_ptr)
     }
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        crate::sys_deallocate(ptr, &layout);
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            unsafe { self.heap.lock().deallocate(ptr, layout) };
+        }
     }
 }
 
 #[global_allocator]
-static ALLOCATOR: KernelAllocator = KernelAllocator;
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: LockedHeap::empty(),
+};
+
+/// Move the program break to make room for at least `size` more bytes
+fn grow(heap: &mut Heap, size: usize) -> bool {
+    let size = size.max(GROW_SIZE);
+
+    // the heap starts at the break on the first allocation
+    let start = if heap.size() == 0 {
+        match sys_brk(None) {
+            Ok(start) => start,
+            Err(_) => return false,
+        }
+    } else {
+        heap.top() as usize
+    };
+
+    match sys_brk(Some(start + size)) {
+        Ok(end) if end == start + size => {}
+        _ => return false,
+    }
+
+    unsafe {
+        if heap.size() == 0 {
+            heap.init(start as *mut u8, size);
+        } else {
+            heap.extend(size);
+        }
+    }
+
+    true
+}
 
 #[cfg(not(test))]
 #[alloc_error_handler]
@@ -0,0 +1,112 @@
+//! Arguments and environment of the running program
+//!
+//! The kernel copies them to the top of the user stack before the program
+//! starts and passes `argc`, `argv` and `envp` to `_start`. They stay there
+//! until the program exits, so the strings are handed out as `&'static str`.
+
+use core::ffi::{CStr, c_char};
+use core::ptr::null;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Record the arguments passed to `_start`
+///
+/// # Safety
+///
+/// `argv` must hold `argc` valid strings and `envp` must be null-terminated,
+/// both as laid out by the kernel.
+#[doc(hidden)]
+pub unsafe fn init(argc: usize, argv: *const *const c_char, envp: *const *const c_char) {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv as *mut _, Ordering::Relaxed);
+    ENVP.store(envp as *mut _, Ordering::Relaxed);
+}
+
+/// Read a string passed by the kernel, invalid UTF-8 reads as empty
+unsafe fn to_str(ptr: *const c_char) -> &'static str {
+    unsafe { CStr::from_ptr(ptr) }.to_str().unwrap_or("")
+}
+
+/// The arguments of the program, starting with its name
+pub fn args() -> Args {
+    let argv = ARGV.load(Ordering::Relaxed);
+    Args {
+        argv,
+        pos: 0,
+        len: if argv.is_null() {
+            0
+        } else {
+            ARGC.load(Ordering::Relaxed)
+        },
+    }
+}
+
+/// The environment of the program as `(key, value)` pairs
+pub fn env() -> Vars {
+    Vars {
+        envp: ENVP.load(Ordering::Relaxed),
+    }
+}
+
+/// Look up the environment variable `key`
+pub fn var(key: &str) -> Option<&'static str> {
+    env().find(|&(k, _)| k == key).map(|(_, v)| v)
+}
+
+pub struct Args {
+    argv: *const *const c_char,
+    pos: usize,
+    len: usize,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.len {
+            return None;
+        }
+
+        let arg = unsafe { to_str(*self.argv.add(self.pos)) };
+        self.pos += 1;
+        Some(arg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.pos;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+pub struct Vars {
+    envp: *const *const c_char,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.envp.is_null() {
+                return None;
+            }
+
+            let ptr = unsafe { *self.envp };
+            if ptr.is_null() {
+                self.envp = null();
+                return None;
+            }
+            self.envp = unsafe { self.envp.add(1) };
+
+            // skip malformed entries without a `=`
+            if let Some(var) = unsafe { to_str(ptr) }.split_once('=') {
+                return Some(var);
+            }
+        }
+    }
+}
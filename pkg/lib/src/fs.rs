@@ -0,0 +1,110 @@
+use crate::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::result::Result;
+
+/// Enumeration of possible methods to seek within a file.
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum SeekFrom {
+    /// Sets the offset to the provided number of bytes.
+    Start(usize),
+
+    /// Sets the offset to the size of the file plus the offset.
+    End(isize),
+
+    /// Sets the offset to the current position plus the offset.
+    Current(isize),
+}
+
+/// A file opened from the kernel's root filesystem.
+///
+/// the file descriptor is closed when the `File` is dropped
+pub struct File {
+    fd: u8,
+}
+
+impl File {
+    /// Open the file at `path` for reading
+    pub fn open(path: &str) -> Result<Self, Errno> {
+        Self::open_with(path, 0)
+    }
+
+    /// Open the file at `path` for writing, truncating or creating it
+    pub fn create(path: &str) -> Result<Self, Errno> {
+        Self::open_with(path, O_WRONLY | O_CREAT | O_TRUNC)
+    }
+
+    /// Open the file at `path` for appending, creating it if needed
+    pub fn append(path: &str) -> Result<Self, Errno> {
+        Self::open_with(path, O_WRONLY | O_CREAT | O_APPEND)
+    }
+
+    /// Open the file at `path` with a combination of the `O_*` flags
+    pub fn open_with(path: &str, flags: usize) -> Result<Self, Errno> {
+        sys_open(path, flags).map(|fd| Self { fd })
+    }
+
+    /// Give up ownership of the fd, it is no longer closed on drop
+    pub fn into_fd(self) -> u8 {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
+    pub fn fd(&self) -> u8 {
+        self.fd
+    }
+
+    /// Read some bytes into `buf`, returns 0 on EOF
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        sys_read(self.fd, buf)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, Errno> {
+        sys_write(self.fd, buf)
+    }
+
+    /// Write all of `buf`, retrying after short writes
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), Errno> {
+        io::write_all(self.fd, buf)
+    }
+
+    /// Seek to an offset, returns the new offset from the start of the file
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<usize, Errno> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as isize, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        sys_seek(self.fd, offset, whence)
+    }
+
+    /// Read all bytes until EOF, appending them to `buf`
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Errno> {
+        let start_len = buf.len();
+        let mut tmp = [0u8; 512];
+        loop {
+            let read = self.read(&mut tmp)?;
+            if read == 0 {
+                break;
+            }
+            buf.extend_from_slice(&tmp[..read]);
+        }
+
+        Ok(buf.len() - start_len)
+    }
+
+    /// Read all bytes until EOF, appending them to `buf` if they are valid UTF-8
+    pub fn read_to_string(&mut self, buf: &mut String) -> Result<usize, Errno> {
+        let mut bytes = Vec::new();
+        let read = self.read_to_end(&mut bytes)?;
+        buf.push_str(core::str::from_utf8(&bytes).map_err(|_| Errno::InvalidArgument)?);
+        Ok(read)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = sys_close(self.fd);
+    }
+}
@@ -39,7 +39,7 @@ impl Stdin {
 
         loop {
             match sys_read(0, &mut one_byte) {
-                Some(1) => {
+                Ok(1) => {
                     let b = one_byte[0];
                     match b {
                         b'\n' | b'\r' => {
@@ -48,9 +48,10 @@ impl Stdin {
                             break;
                         }
                         0x03 => {
-                            // Ctrl-C
+                            // Ctrl-C, terminates the process unless SIGINT is handled
                             line.clear();
                             self::print!("^C\n");
+                            let _ = signal::raise(Signal::Int);
                             break;
                         }
                         0x04 => {
@@ -94,7 +95,7 @@ impl Stdout {
     }
 
     pub fn write(&self, s: &str) {
-        sys_write(1, s.as_bytes());
+        let _ = write_all(1, s.as_bytes());
     }
 }
 
@@ -104,10 +105,21 @@ impl Stderr {
     }
 
     pub fn write(&self, s: &str) {
-        sys_write(2, s.as_bytes());
+        let _ = write_all(2, s.as_bytes());
     }
 }
 
+/// Write all of `buf` to `fd`, a pipe may take only part of it at a time
+pub fn write_all(fd: u8, mut buf: &[u8]) -> core::result::Result<(), Errno> {
+    while !buf.is_empty() {
+        match sys_write(fd, buf)? {
+            0 => return Err(Errno::IoError),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
 pub fn stdin() -> Stdin {
     Stdin::new()
 }
@@ -11,7 +11,10 @@ extern crate syscall_def;
 #[macro_use]
 pub mod io;
 pub mod allocator;
+pub mod env;
+pub mod fs;
 pub mod rand;
+pub mod signal;
 pub mod sync;
 pub extern crate alloc;
 
@@ -21,7 +24,9 @@ use core::fmt::*;
 
 pub use alloc::*;
 pub use chrono::*;
+pub use env::{args, env};
 pub use io::*;
+pub use signal::{SigHandler, SigSet, Signal};
 pub use sync::*;
 pub use syscall::*;
 
@@ -65,30 +70,25 @@ pub fn _err(args: Arguments) {
 }
 
 pub fn sleep(millisecs: u64) {
-    let start = sys_time();
-    let dur = millisecs;
-    let mut current = start;
-    while current - start < dur {
-        current = sys_time();
-    }
+    let _ = sys_sleep(Duration::from_millis(millisecs).as_nanos() as u64);
 }
 
-pub fn fork() -> u16 {
+pub fn fork() -> core::result::Result<u16, Errno> {
     sys_fork()
 }
 
-pub fn sys_ls(path: &str) {
-    sys_list_dir(path);
+pub fn sys_ls(path: &str) -> core::result::Result<(), Errno> {
+    sys_list_dir(path)
 }
 
 pub fn dir_exists(path: &str) -> bool {
-    sys_exists(path)
+    sys_exists(path).unwrap_or(false)
 }
 
-pub fn cat(path: &str) -> usize {
+pub fn cat(path: &str) -> core::result::Result<usize, Errno> {
     sys_cat(path)
 }
 
-pub fn brk(addr: Option<usize>) -> core::result::Result<usize, &'static str> {
+pub fn brk(addr: Option<usize>) -> core::result::Result<usize, Errno> {
     sys_brk(addr)
 }
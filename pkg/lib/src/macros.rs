@@ -6,7 +6,12 @@ use crate::syscall::*;
 macro_rules! entry {
     ($fn:ident) => {
         #[unsafe(export_name = "_start")]
-        pub extern "C" fn __impl_start() {
+        pub extern "C" fn __impl_start(
+            argc: usize,
+            argv: *const *const core::ffi::c_char,
+            envp: *const *const core::ffi::c_char,
+        ) {
+            unsafe { lib::env::init(argc, argv, envp) };
             lib::init();
             let ret = $fn();
             // FIXME: after syscall, add lib::sys_exit(ret);
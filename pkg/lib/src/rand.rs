@@ -4,7 +4,7 @@ use rand_chacha::ChaCha20Rng;
 
 #[inline(always)]
 pub fn new_rng() -> ChaCha20Rng {
-    let seed = unsafe { sys_time() };
+    let seed = sys_time().unwrap_or(0);
     ChaCha20Rng::seed_from_u64(seed)
 }
 
@@ -0,0 +1,79 @@
+//! Signal handlers and masks
+//!
+//! Handlers are plain functions, the kernel enters them through
+//! `trampoline` which returns to the interrupted code with `sys_sigreturn`.
+
+use crate::syscall::*;
+use syscall_def::signal::{SIG_DFL, SIG_IGN, SigMaskHow};
+
+pub use syscall_def::signal::{DefaultAction, SigSet, Signal};
+
+/// How a signal is handled
+#[derive(Clone, Copy, Debug)]
+pub enum SigHandler {
+    Default,
+    Ignore,
+    Handler(fn(Signal)),
+}
+
+impl SigHandler {
+    fn to_raw(self) -> usize {
+        match self {
+            SigHandler::Default => SIG_DFL,
+            SigHandler::Ignore => SIG_IGN,
+            SigHandler::Handler(handler) => handler as usize,
+        }
+    }
+
+    fn from_raw(raw: usize) -> Self {
+        match raw {
+            SIG_DFL => SigHandler::Default,
+            SIG_IGN => SigHandler::Ignore,
+            // only ever installed from a `fn(Signal)` by `set_handler`
+            handler => {
+                SigHandler::Handler(unsafe { core::mem::transmute::<usize, fn(Signal)>(handler) })
+            }
+        }
+    }
+}
+
+extern "C" fn trampoline(signo: usize, handler: usize, frame: usize) -> ! {
+    if let (Ok(sig), SigHandler::Handler(handler)) =
+        (Signal::try_from(signo), SigHandler::from_raw(handler))
+    {
+        handler(sig);
+    }
+
+    sys_sigreturn(frame)
+}
+
+/// Set how `sig` is handled, returns the previous setting
+pub fn set_handler(sig: Signal, handler: SigHandler) -> Result<SigHandler, Errno> {
+    let trampoline: extern "C" fn(usize, usize, usize) -> ! = trampoline;
+    sys_sigaction(sig, handler.to_raw(), trampoline as usize).map(SigHandler::from_raw)
+}
+
+/// Send `sig` to the process `pid`
+pub fn kill(pid: u16, sig: Signal) -> Result<(), Errno> {
+    sys_kill(pid, sig)
+}
+
+/// Send `sig` to the current process
+pub fn raise(sig: Signal) -> Result<(), Errno> {
+    sys_kill(sys_get_pid(), sig)
+}
+
+/// Hold back `set` until it is unblocked, returns the previous mask
+pub fn block(set: SigSet) -> Result<SigSet, Errno> {
+    sys_sigprocmask(SigMaskHow::Block, set)
+}
+
+/// Deliver `set` again, returns the previous mask
+pub fn unblock(set: SigSet) -> Result<SigSet, Errno> {
+    sys_sigprocmask(SigMaskHow::Unblock, set)
+}
+
+/// Replace the blocked set, returns the previous mask
+pub fn set_mask(set: SigSet) -> Result<SigSet, Errno> {
+    sys_sigprocmask(SigMaskHow::SetMask, set)
+}
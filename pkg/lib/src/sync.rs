@@ -48,23 +48,23 @@ impl Semaphore {
 
     #[inline(always)]
     pub fn init(&self, value: usize) -> bool {
-        sys_new_sem(self.key, value) == 1
+        sys_new_sem(self.key, value).is_ok()
     }
 
     /* FIXME: other functions with syscall... */
     #[inline(always)]
     pub fn remove(&self) -> bool {
-        sys_remove_sem(self.key) == 1
+        sys_remove_sem(self.key).is_ok()
     }
 
     #[inline(always)]
     pub fn signal(&self) -> bool {
-        sys_sem_signal(self.key) == 0
+        sys_sem_signal(self.key).is_ok()
     }
 
     #[inline(always)]
     pub fn wait(&self) -> bool {
-        sys_sem_wait(self.key) == 0
+        sys_sem_wait(self.key).is_ok()
     }
 }
 
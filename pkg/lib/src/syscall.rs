@@ -1,51 +1,136 @@
-use syscall_def::Syscall;
+use alloc::vec::Vec;
+use core::time::Duration;
+use syscall_def::errno::decode;
+use syscall_def::signal::SigMaskHow;
+use syscall_def::{ExecArgs, MmapArgs, SigSet, Signal, Syscall, WAIT_ANY, WNOHANG};
+
+pub use syscall_def::{
+    ClockId, Errno, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, O_APPEND, O_CLOEXEC, O_CREAT, O_TRUNC,
+    O_WRONLY, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE, SyscallResult,
+};
 
 #[inline(always)]
-pub fn sys_write(fd: u8, buf: &[u8]) -> Option<usize> {
-    let ret = syscall!(
+pub fn sys_write(fd: u8, buf: &[u8]) -> Result<usize, Errno> {
+    decode(syscall!(
         Syscall::Write,
         fd as u64,
         buf.as_ptr() as u64,
         buf.len() as u64
-    ) as isize;
-    if ret.is_negative() {
-        None
-    } else {
-        Some(ret as usize)
-    }
+    ))
 }
 
 #[inline(always)]
-pub fn sys_read(fd: u8, buf: &mut [u8]) -> Option<usize> {
-    let ret = syscall!(
+pub fn sys_read(fd: u8, buf: &mut [u8]) -> Result<usize, Errno> {
+    decode(syscall!(
         Syscall::Read,
         fd as u64,
         buf.as_ptr() as u64,
         buf.len() as u64
-    ) as isize;
-    if ret.is_negative() {
-        None
-    } else {
-        Some(ret as usize)
-    }
+    ))
 }
 
-pub fn sys_time() -> u64 {
-    syscall!(Syscall::Time) as u64
+#[inline(always)]
+pub fn sys_open(path: &str, flags: usize) -> Result<u8, Errno> {
+    decode(syscall!(
+        Syscall::Open,
+        path.as_ptr() as u64,
+        path.len() as u64,
+        flags as u64
+    ))
+    .map(|fd| fd as u8)
 }
 
 #[inline(always)]
-pub fn sys_wait_pid(pid: u16) -> isize {
-    // FIXME: try to get the return value for process
-    //        loop until the process is finished
-    let mut ret = -1;
-    loop {
-        ret = syscall!(Syscall::WaitPid, pid as u64) as isize;
-        if ret != 20050615 {
-            break;
-        }
-    }
-    ret as isize
+pub fn sys_close(fd: u8) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Close, fd as u64)).map(|_| ())
+}
+
+/// Duplicate `fd` onto the lowest free fd, `flags` is 0 or `O_CLOEXEC`
+#[inline(always)]
+pub fn sys_dup(fd: u8, flags: usize) -> Result<u8, Errno> {
+    decode(syscall!(Syscall::Dup, fd as u64, flags as u64)).map(|fd| fd as u8)
+}
+
+/// Make `new` refer to what `old` refers to, closing `new` first if it is open
+///
+/// `flags` is 0 or `O_CLOEXEC`.
+#[inline(always)]
+pub fn sys_dup2(old: u8, new: u8, flags: usize) -> Result<u8, Errno> {
+    decode(syscall!(
+        Syscall::Dup2,
+        old as u64,
+        new as u64,
+        flags as u64
+    ))
+    .map(|fd| fd as u8)
+}
+
+/// Create a pipe, returns the fds of its read and write ends
+#[inline(always)]
+pub fn sys_pipe() -> Result<(u8, u8), Errno> {
+    let mut fds = [0u8; 2];
+    decode(syscall!(Syscall::Pipe, fds.as_mut_ptr() as u64))?;
+    Ok((fds[0], fds[1]))
+}
+
+#[inline(always)]
+pub fn sys_seek(fd: u8, offset: isize, whence: u8) -> Result<usize, Errno> {
+    decode(syscall!(
+        Syscall::Seek,
+        fd as u64,
+        offset as u64,
+        whence as u64
+    ))
+}
+
+#[inline(always)]
+pub fn sys_time() -> Result<u64, Errno> {
+    decode(syscall!(Syscall::Time)).map(|ms| ms as u64)
+}
+
+/// Read the clock `clock`, realtime is relative to the Unix epoch
+#[inline(always)]
+pub fn sys_clock_gettime(clock: ClockId) -> Result<Duration, Errno> {
+    decode(syscall!(Syscall::ClockGetTime, clock as usize))
+        .map(|ns| Duration::from_nanos(ns as u64))
+}
+
+/// Block the current process for at least `ns` nanoseconds
+#[inline(always)]
+pub fn sys_sleep(ns: u64) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Sleep, ns)).map(|_| ())
+}
+
+#[inline(always)]
+fn sys_wait(pid: isize, options: usize) -> Result<(u16, isize), Errno> {
+    let mut status: isize = 0;
+    let pid = decode(syscall!(
+        Syscall::WaitPid,
+        pid as u64,
+        &mut status as *mut isize as u64,
+        options as u64
+    ))?;
+    Ok((pid as u16, status))
+}
+
+/// Wait for the child `pid` to exit and return its exit code
+#[inline(always)]
+pub fn sys_wait_pid(pid: u16) -> Result<isize, Errno> {
+    sys_wait(pid as isize, 0).map(|(_, status)| status)
+}
+
+/// Wait for any child to exit, returns its pid and exit code
+#[inline(always)]
+pub fn sys_wait_any() -> Result<(u16, isize), Errno> {
+    sys_wait(WAIT_ANY, 0)
+}
+
+/// Reap the child `pid` (or any child if `None`) without blocking,
+/// returns `None` if it is still running
+#[inline(always)]
+pub fn sys_try_wait_pid(pid: Option<u16>) -> Result<Option<(u16, isize)>, Errno> {
+    let pid = pid.map_or(WAIT_ANY, |pid| pid as isize);
+    sys_wait(pid, WNOHANG).map(|(pid, status)| (pid != 0).then_some((pid, status)))
 }
 
 #[inline(always)]
@@ -58,19 +143,118 @@ pub fn sys_stat() {
     syscall!(Syscall::Stat);
 }
 
+/// Map `len` bytes of memory with the `PROT_*` protection and `MAP_*` flags,
+/// anonymous unless `fd` and `offset` select a file to copy
 #[inline(always)]
-pub fn sys_allocate(layout: &core::alloc::Layout) -> *mut u8 {
-    syscall!(Syscall::Allocate, layout as *const _) as *mut u8
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: u8,
+    offset: usize,
+) -> Result<*mut u8, Errno> {
+    let args = MmapArgs {
+        addr,
+        len,
+        prot,
+        flags,
+        fd: fd as usize,
+        offset,
+    };
+    decode(syscall!(Syscall::Mmap, &args as *const MmapArgs as u64)).map(|addr| addr as *mut u8)
 }
 
+/// Unmap `[addr, addr + len)` mapped by `sys_mmap`
 #[inline(always)]
-pub fn sys_deallocate(ptr: *mut u8, layout: &core::alloc::Layout) -> usize {
-    syscall!(Syscall::Deallocate, ptr, layout as *const _)
+pub fn sys_munmap(addr: *mut u8, len: usize) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Munmap, addr as u64, len as u64)).map(|_| ())
 }
 
+/// Change the protection of `[addr, addr + len)` to `prot`, any `PROT_*`
+/// combination except writable and executable at once
 #[inline(always)]
-pub fn sys_spawn(name: &str) -> u16 {
-    syscall!(Syscall::Spawn, name.as_ptr() as u64, name.len() as u64) as u16
+pub fn sys_mprotect(addr: *mut u8, len: usize, prot: usize) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Mprotect, addr as u64, len as u64, prot as u64)).map(|_| ())
+}
+
+#[inline(always)]
+pub fn sys_spawn(name: &str) -> Result<u16, Errno> {
+    // the kernel always reads the third argument, 0 passes no `ExecArgs`
+    decode(syscall!(
+        Syscall::Spawn,
+        name.as_ptr() as u64,
+        name.len() as u64,
+        0
+    ))
+    .map(|pid| pid as u16)
+}
+
+/// Spawn the app `name` with the arguments `argv`, starting with the name,
+/// and the `KEY=VALUE` environment `envp`, or the current one if `None`
+pub fn sys_spawn_args(name: &str, argv: &[&str], envp: Option<&[&str]>) -> Result<u16, Errno> {
+    let argv = pack_strs(argv);
+    let envp = envp.map(pack_strs);
+    let args = exec_args(&argv, envp.as_deref());
+
+    decode(syscall!(
+        Syscall::Spawn,
+        name.as_ptr() as u64,
+        name.len() as u64,
+        &args as *const ExecArgs as u64
+    ))
+    .map(|pid| pid as u16)
+}
+
+/// Replace the current program with the app `name`,
+/// only returns if it cannot be executed
+#[inline(always)]
+pub fn sys_exec(name: &str) -> Errno {
+    match decode(syscall!(
+        Syscall::Exec,
+        name.as_ptr() as u64,
+        name.len() as u64,
+        0
+    )) {
+        Err(errno) => errno,
+        Ok(_) => unreachable!("exec returned without an error"),
+    }
+}
+
+/// Like `sys_exec`, passing `argv` and `envp` like `sys_spawn_args`
+pub fn sys_exec_args(name: &str, argv: &[&str], envp: Option<&[&str]>) -> Errno {
+    let argv = pack_strs(argv);
+    let envp = envp.map(pack_strs);
+    let args = exec_args(&argv, envp.as_deref());
+
+    match decode(syscall!(
+        Syscall::Exec,
+        name.as_ptr() as u64,
+        name.len() as u64,
+        &args as *const ExecArgs as u64
+    )) {
+        Err(errno) => errno,
+        Ok(_) => unreachable!("exec returned without an error"),
+    }
+}
+
+/// Pack strings as NUL-terminated bytes for `ExecArgs`
+fn pack_strs(strs: &[&str]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(strs.iter().map(|s| s.len() + 1).sum());
+    for s in strs {
+        buf.extend_from_slice(s.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+fn exec_args(argv: &[u8], envp: Option<&[u8]>) -> ExecArgs {
+    ExecArgs {
+        argv_ptr: argv.as_ptr() as usize,
+        argv_len: argv.len(),
+        envp_ptr: envp.map_or(0, |envp| envp.as_ptr() as usize),
+        envp_len: envp.map_or(0, |envp| envp.len()),
+    }
 }
 
 #[inline(always)]
@@ -85,50 +269,108 @@ pub fn sys_exit(code: isize) -> ! {
 }
 
 #[inline(always)]
-pub fn sys_fork() -> u16 {
-    syscall!(Syscall::Fork) as u16
+pub fn sys_kill(pid: u16, sig: Signal) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Kill, pid as u64, sig as u64)).map(|_| ())
 }
 
+/// Allow process `pid` to access `count` I/O ports from `from`
+///
+/// Only processes started by the kernel may grant ports, other port
+/// accesses from user mode raise `SIGSEGV`.
 #[inline(always)]
-pub fn sys_new_sem(key: u32, init_value: usize) -> usize {
-    syscall!(Syscall::Sem, 0, key, init_value)
+pub fn sys_ioperm(pid: u16, from: u16, count: usize) -> Result<(), Errno> {
+    decode(syscall!(
+        Syscall::IoPerm,
+        pid as u64,
+        from as u64,
+        count as u64
+    ))
+    .map(|_| ())
 }
 
+/// Install a raw `handler` address for `sig`, or `SIG_DFL` / `SIG_IGN`
+///
+/// Handlers are entered through `trampoline(signo, handler, frame)`,
+/// which must end with `sys_sigreturn(frame)`. Returns the old handler.
 #[inline(always)]
-pub fn sys_remove_sem(key: u32) -> usize {
-    syscall!(Syscall::Sem, 1, key)
+pub fn sys_sigaction(sig: Signal, handler: usize, trampoline: usize) -> Result<usize, Errno> {
+    decode(syscall!(
+        Syscall::SigAction,
+        sig as u64,
+        handler as u64,
+        trampoline as u64
+    ))
 }
 
 #[inline(always)]
-pub fn sys_sem_signal(key: u32) -> usize {
-    syscall!(Syscall::Sem, 2, key)
+pub fn sys_sigprocmask(how: SigMaskHow, set: SigSet) -> Result<SigSet, Errno> {
+    decode(syscall!(Syscall::SigProcMask, how as u64, set.bits()))
+        .map(|old| SigSet::from_bits(old as u64))
 }
 
+/// Resume the context saved in the signal `frame`
 #[inline(always)]
-pub fn sys_sem_wait(key: u32) -> usize {
-    syscall!(Syscall::Sem, 3, key)
+pub fn sys_sigreturn(frame: usize) -> ! {
+    syscall!(Syscall::SigReturn, frame as u64);
+    unreachable!("The signal frame should be restored by now.")
 }
 
+/// Returns the pid of the child in the parent, and 0 in the child
 #[inline(always)]
-pub fn sys_list_dir(path: &str) -> usize {
-    syscall!(Syscall::ListDir, path.as_ptr() as u64, path.len() as u64) as usize
+pub fn sys_fork() -> Result<u16, Errno> {
+    decode(syscall!(Syscall::Fork)).map(|pid| pid as u16)
 }
 
 #[inline(always)]
-pub fn sys_exists(path: &str) -> bool {
-    syscall!(Syscall::Exists, path.as_ptr() as u64, path.len() as u64) != 0
+pub fn sys_new_sem(key: u32, init_value: usize) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Sem, 0, key, init_value)).map(|_| ())
 }
 
 #[inline(always)]
-pub fn sys_cat(path: &str) -> usize {
-    syscall!(Syscall::Cat, path.as_ptr() as u64, path.len() as u64)
+pub fn sys_remove_sem(key: u32) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Sem, 1, key)).map(|_| ())
 }
 
 #[inline(always)]
-pub fn sys_brk(addr: Option<usize>) -> Result<usize, &'static str> {
-    const BRK_FAILED: usize = !0;
-    match syscall!(Syscall::Brk, addr.unwrap_or(0)) {
-        BRK_FAILED => Err("brk failed"),
-        ret => Ok(ret as usize),
-    }
+pub fn sys_sem_signal(key: u32) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Sem, 2, key)).map(|_| ())
+}
+
+#[inline(always)]
+pub fn sys_sem_wait(key: u32) -> Result<(), Errno> {
+    decode(syscall!(Syscall::Sem, 3, key)).map(|_| ())
+}
+
+#[inline(always)]
+pub fn sys_list_dir(path: &str) -> Result<(), Errno> {
+    decode(syscall!(
+        Syscall::ListDir,
+        path.as_ptr() as u64,
+        path.len() as u64
+    ))
+    .map(|_| ())
+}
+
+#[inline(always)]
+pub fn sys_exists(path: &str) -> Result<bool, Errno> {
+    decode(syscall!(
+        Syscall::Exists,
+        path.as_ptr() as u64,
+        path.len() as u64
+    ))
+    .map(|ret| ret != 0)
+}
+
+#[inline(always)]
+pub fn sys_cat(path: &str) -> Result<usize, Errno> {
+    decode(syscall!(
+        Syscall::Cat,
+        path.as_ptr() as u64,
+        path.len() as u64
+    ))
+}
+
+#[inline(always)]
+pub fn sys_brk(addr: Option<usize>) -> Result<usize, Errno> {
+    decode(syscall!(Syscall::Brk, addr.unwrap_or(0)))
 }
@@ -26,6 +26,11 @@ pub trait FileSystem: Debug + Sync + Send {
         Err(FsError::NotSupported)
     }
 
+    /// Opens the file at this path for writing from the start, keeping its contents
+    fn open_file_for_write(&self, _path: &str) -> Result<FileHandle> {
+        Err(FsError::NotSupported)
+    }
+
     /// Opens the file at this path for appending
     fn append_file(&self, _path: &str) -> Result<FileHandle> {
         Err(FsError::NotSupported)
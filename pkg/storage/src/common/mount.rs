@@ -40,6 +40,21 @@ impl FileSystem for Mount {
     fn exists(&self, path: &str) -> Result<bool> {
         self.fs.exists(self.trim_mount_point(path))
     }
+
+    #[inline]
+    fn create_file(&self, path: &str) -> Result<FileHandle> {
+        self.fs.create_file(self.trim_mount_point(path))
+    }
+
+    #[inline]
+    fn open_file_for_write(&self, path: &str) -> Result<FileHandle> {
+        self.fs.open_file_for_write(self.trim_mount_point(path))
+    }
+
+    #[inline]
+    fn append_file(&self, path: &str) -> Result<FileHandle> {
+        self.fs.append_file(self.trim_mount_point(path))
+    }
 }
 
 impl core::fmt::Debug for Mount {
@@ -23,6 +23,15 @@ pub struct DirEntry {
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct Cluster(pub u32);
 
+/// Where a directory entry is stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntryPos {
+    /// The sector holding the entry
+    pub sector: usize,
+    /// Byte offset of the entry in the sector
+    pub offset: usize,
+}
+
 bitflags! {
     /// File Attributes
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -95,6 +104,33 @@ impl DirEntry {
         })
     }
 
+    /// An empty file, without clusters or timestamps
+    pub fn new_file(filename: ShortFileName) -> DirEntry {
+        let time = DateTime::from_timestamp_millis(0).unwrap();
+
+        DirEntry {
+            filename,
+            modified_time: time,
+            created_time: time,
+            accessed_time: time,
+            cluster: Cluster::EMPTY,
+            attributes: Attributes::ARCHIVE,
+            size: 0,
+        }
+    }
+
+    /// Store the name, attributes, cluster and size into the raw entry `data`
+    ///
+    /// The timestamps in `data` are kept as they are.
+    pub fn write_to(&self, data: &mut [u8]) {
+        data[..8].copy_from_slice(&self.filename.name);
+        data[8..11].copy_from_slice(&self.filename.ext);
+        data[11] = self.attributes.bits();
+        data[20..22].copy_from_slice(&((self.cluster.0 >> 16) as u16).to_le_bytes());
+        data[26..28].copy_from_slice(&(self.cluster.0 as u16).to_le_bytes());
+        data[28..32].copy_from_slice(&self.size.to_le_bytes());
+    }
+
     pub fn as_meta(&self) -> Metadata {
         self.into()
     }
@@ -342,4 +378,26 @@ mod tests {
 
         println!("{:#?}", res);
     }
+
+    #[test]
+    fn test_dir_entry_write() {
+        let mut data = hex_literal::hex!(
+            "4b 45 52 4e 45 4c 20 20 45 4c 46 20 00 00 0f be
+             d0 50 d0 50 00 00 0f be d0 50 02 00 f0 e4 0e 00"
+        );
+
+        let mut entry = DirEntry::parse(&data).unwrap();
+        entry.filename = ShortFileName::parse("log.txt").unwrap();
+        entry.cluster = Cluster(0x12345);
+        entry.size = 42;
+        entry.write_to(&mut data);
+
+        let res = DirEntry::parse(&data).unwrap();
+        assert_eq!(res, entry);
+        assert_eq!(res.filename(), "LOG.TXT");
+        assert_eq!(
+            res.modified_time,
+            Utc.with_ymd_and_hms(2020, 6, 16, 23, 48, 30).unwrap()
+        );
+    }
 }
@@ -12,16 +12,27 @@ pub struct File {
     current_cluster: Cluster,
     /// DirEntry of this file
     entry: DirEntry,
+    /// Where the DirEntry is stored, updated as the file grows
+    entry_pos: DirEntryPos,
+    /// Whether the file is open for writing
+    writable: bool,
     /// The file system handle that contains this file
     handle: Fat16Handle,
 }
 
 impl File {
-    pub fn new(handle: Fat16Handle, entry: DirEntry) -> Self {
+    pub fn new(
+        handle: Fat16Handle,
+        entry: DirEntry,
+        entry_pos: DirEntryPos,
+        writable: bool,
+    ) -> Self {
         Self {
             offset: 0,
             current_cluster: entry.cluster,
             entry,
+            entry_pos,
+            writable,
             handle,
         }
     }
@@ -29,6 +40,63 @@ impl File {
     pub fn length(&self) -> usize {
         self.entry.size as usize
     }
+
+    pub fn entry(&self) -> &DirEntry {
+        &self.entry
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.handle.bpb.sectors_per_cluster() as usize * Block512::size()
+    }
+
+    /// Write as much of `buf` as fits into the sector at `offset`
+    fn write_sector(&mut self, buf: &[u8]) -> Result<usize> {
+        let blk_size = Block512::size();
+        let clus_size = self.cluster_size();
+
+        // `current_cluster` stays on the last cluster at the end of the file,
+        // writing on from a cluster boundary there needs the next one
+        if self.entry.cluster == Cluster::EMPTY {
+            let cluster = self.handle.alloc_cluster(None)?;
+            self.entry.cluster = cluster;
+            self.current_cluster = cluster;
+        } else if self.offset.is_multiple_of(clus_size)
+            && self.offset > 0
+            && self.offset >= self.length()
+        {
+            self.current_cluster = match self.handle.next_cluster(self.current_cluster)? {
+                Cluster::END_OF_FILE => self.handle.alloc_cluster(Some(self.current_cluster))?,
+                c => c,
+            };
+        }
+
+        let in_clus_off = self.offset % clus_size;
+        let sector = self.handle.cluster_to_sector(&self.current_cluster) + in_clus_off / blk_size;
+        let in_sector_off = in_clus_off % blk_size;
+        let len = core::cmp::min(blk_size - in_sector_off, buf.len());
+
+        let mut block = Block::default();
+        if len < blk_size {
+            self.handle.inner.read_block(sector, &mut block)?;
+        }
+        block.as_mut()[in_sector_off..in_sector_off + len].copy_from_slice(&buf[..len]);
+        self.handle.inner.write_block(sector, &block)?;
+
+        self.offset += len;
+        if self.offset > self.length() {
+            self.entry.size = self.offset as u32;
+        }
+
+        // like `read`, move on once a cluster inside the file is used up
+        if self.offset.is_multiple_of(clus_size) && self.offset < self.length() {
+            self.current_cluster = match self.handle.next_cluster(self.current_cluster)? {
+                Cluster::END_OF_FILE => return Err(FsError::BadCluster),
+                c => c,
+            };
+        }
+
+        Ok(len)
+    }
 }
 
 impl Read for File {
@@ -92,7 +160,9 @@ impl Read for File {
                 in_sector_off = 0;
             }
 
-            if read_bytes < to_read {
+            // move on as soon as the current cluster is used up,
+            // so that `current_cluster` always matches `offset`
+            if self.offset.is_multiple_of(clus_size) && self.offset < file_len {
                 cluster = match self.handle.next_cluster(cluster)? {
                     Cluster::END_OF_FILE => break,
                     c => {
@@ -107,20 +177,329 @@ impl Read for File {
     }
 }
 
-// NOTE: `Seek` trait is not required for this lab
 impl Seek for File {
     fn seek(&mut self, pos: SeekFrom) -> Result<usize> {
-        unimplemented!()
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.length().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+        }
+        .ok_or(FsError::InvalidOffset)?;
+
+        // seeking past the end is clamped, reads will return EOF there
+        let new_offset = core::cmp::min(new_offset, self.length());
+
+        let clus_size = self.handle.bpb.sectors_per_cluster() as usize * Block512::size();
+        let mut skip = new_offset / clus_size;
+        if new_offset == self.length() && new_offset.is_multiple_of(clus_size) {
+            // stay on the last cluster instead of walking off the chain
+            skip = skip.saturating_sub(1);
+        }
+
+        // walk the cluster chain from the start of the file
+        let mut cluster = self.entry.cluster;
+        for _ in 0..skip {
+            cluster = match self.handle.next_cluster(cluster)? {
+                Cluster::END_OF_FILE => break,
+                c => c,
+            };
+        }
+
+        self.current_cluster = cluster;
+        self.offset = new_offset;
+
+        Ok(new_offset)
     }
 }
 
-// NOTE: `Write` trait is not required for this lab
 impl Write for File {
-    fn write(&mut self, _buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.writable {
+            return Err(FsError::ReadOnly);
+        }
+
+        let (cluster, size) = (self.entry.cluster, self.entry.size);
+
+        let mut written = 0;
+        let ret = loop {
+            if written == buf.len() {
+                break Ok(written);
+            }
+            match self.write_sector(&buf[written..]) {
+                Ok(len) => written += len,
+                // report the bytes that made it before the disk filled up
+                Err(_) if written > 0 => break Ok(written),
+                Err(e) => break Err(e),
+            }
+        };
+
+        // record the new size and first cluster, even after a partial write
+        if (self.entry.cluster, self.entry.size) != (cluster, size) {
+            self.handle.write_entry(&self.entry_pos, &self.entry)?;
+        }
+
+        ret
     }
 
+    // NOTE: blocks are written through, there is nothing to flush
     fn flush(&mut self) -> Result<()> {
-        unimplemented!()
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spin::Mutex;
+
+    /// A disk kept in memory
+    struct MemDisk(Mutex<Vec<Block512>>);
+
+    impl BlockDevice<Block512> for MemDisk {
+        fn block_count(&self) -> Result<usize> {
+            Ok(self.0.lock().len())
+        }
+
+        fn read_block(&self, offset: usize, block: &mut Block512) -> Result<()> {
+            let disk = self.0.lock();
+            *block = disk.get(offset).ok_or(FsError::InvalidOffset)?.clone();
+            Ok(())
+        }
+
+        fn write_block(&self, offset: usize, block: &Block512) -> Result<()> {
+            let mut disk = self.0.lock();
+            *disk.get_mut(offset).ok_or(FsError::InvalidOffset)? = block.clone();
+            Ok(())
+        }
+    }
+
+    const SECTORS: usize = 64;
+    const FAT_SECTOR: usize = 1;
+    const ROOT_DIR_SECTOR: usize = 2;
+    const DATA_SECTOR: usize = 3;
+
+    /// A volume with one sector per cluster and `files` in the root directory
+    ///
+    /// Each file is stored in the clusters listed with it.
+    fn volume(files: &[(&str, &[u8], &[u16])]) -> Fat16 {
+        let mut disk = vec![Block512::default(); SECTORS];
+
+        let bpb = disk[0].as_mut();
+        bpb[0x0b..0x0d].copy_from_slice(&512u16.to_le_bytes());
+        bpb[0x0d] = 1; // sectors per cluster
+        bpb[0x0e..0x10].copy_from_slice(&1u16.to_le_bytes()); // reserved sectors
+        bpb[0x10] = 1; // FAT count
+        bpb[0x11..0x13].copy_from_slice(&16u16.to_le_bytes()); // root entries
+        bpb[0x13..0x15].copy_from_slice(&(SECTORS as u16).to_le_bytes());
+        bpb[0x16..0x18].copy_from_slice(&1u16.to_le_bytes()); // sectors per FAT
+        bpb[0x1fe..0x200].copy_from_slice(&0xAA55u16.to_le_bytes());
+
+        for (i, (name, data, clusters)) in files.iter().enumerate() {
+            let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+            let entry = &mut disk[ROOT_DIR_SECTOR].as_mut()[i * DirEntry::LEN..][..DirEntry::LEN];
+            entry[..11].fill(b' ');
+            entry[..base.len()].copy_from_slice(base.as_bytes());
+            entry[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+            entry[11] = Attributes::ARCHIVE.bits();
+            entry[26..28].copy_from_slice(&clusters[0].to_le_bytes());
+            entry[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+
+            for (j, &cluster) in clusters.iter().enumerate() {
+                let next = clusters.get(j + 1).copied().unwrap_or(0xFFFF);
+                let fat = &mut disk[FAT_SECTOR].as_mut()[cluster as usize * 2..][..2];
+                fat.copy_from_slice(&next.to_le_bytes());
+
+                let chunk = data.chunks(BLOCK_SIZE).nth(j).unwrap_or(&[]);
+                let sector = DATA_SECTOR + cluster as usize - 2;
+                disk[sector].as_mut()[..chunk.len()].copy_from_slice(chunk);
+            }
+        }
+
+        Fat16::new(MemDisk(Mutex::new(disk)))
+    }
+
+    fn content(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    #[test]
+    fn test_read_across_clusters() {
+        // interleave the clusters of two files, so that a read running
+        // past the end of a cluster lands in the other file
+        let a = content(1300, 0x5a);
+        let b = content(1000, 0xa5);
+        let fs = volume(&[("A.TXT", &a, &[2, 4, 6]), ("B.TXT", &b, &[3, 5])]);
+
+        let mut file = fs.open_file("a.txt").unwrap();
+
+        let mut buf = vec![0; 500];
+        assert_eq!(file.read(&mut buf).unwrap(), 500);
+        assert_eq!(buf, a[..500]);
+
+        // crosses from cluster 2 into cluster 4
+        let mut buf = vec![0; 100];
+        assert_eq!(file.read(&mut buf).unwrap(), 100);
+        assert_eq!(buf, a[500..600]);
+
+        // ends exactly on the boundary of cluster 4, then reads on in 6
+        let mut buf = vec![0; 424];
+        assert_eq!(file.read(&mut buf).unwrap(), 424);
+        assert_eq!(buf, a[600..1024]);
+
+        let mut buf = vec![0; 1000];
+        assert_eq!(file.read(&mut buf).unwrap(), 276);
+        assert_eq!(buf[..276], a[1024..]);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+        let mut buf = Vec::new();
+        assert_eq!(
+            fs.open_file("b.txt").unwrap().read_all(&mut buf).unwrap(),
+            1000
+        );
+        assert_eq!(buf, b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let a = content(1300, 0x5a);
+        let b = content(1000, 0xa5);
+        let fs = volume(&[("A.TXT", &a, &[2, 4, 6]), ("B.TXT", &b, &[3, 5])]);
+
+        let mut file = fs.open_file("a.txt").unwrap();
+        let mut buf = vec![0; 200];
+
+        // into the middle of the last cluster
+        assert_eq!(file.seek(SeekFrom::Start(1100)).unwrap(), 1100);
+        assert_eq!(file.read(&mut buf).unwrap(), 200);
+        assert_eq!(buf, a[1100..]);
+
+        // back across a cluster boundary
+        assert_eq!(file.seek(SeekFrom::Current(-500)).unwrap(), 800);
+        assert_eq!(file.read(&mut buf).unwrap(), 200);
+        assert_eq!(buf, a[800..1000]);
+
+        assert_eq!(file.seek(SeekFrom::End(-100)).unwrap(), 1200);
+        assert_eq!(file.read(&mut buf).unwrap(), 100);
+        assert_eq!(buf[..100], a[1200..]);
+    }
+
+    #[test]
+    fn test_seek_out_of_range() {
+        let a = content(1024, 0x5a);
+        let fs = volume(&[("A.TXT", &a, &[2, 3])]);
+
+        let mut file = fs.open_file("a.txt").unwrap();
+        let mut buf = vec![0; 100];
+
+        // past the end is clamped to the end
+        assert_eq!(file.seek(SeekFrom::Start(5000)).unwrap(), 1024);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        assert_eq!(file.seek(SeekFrom::End(10)).unwrap(), 1024);
+        assert_eq!(file.seek(SeekFrom::Current(10)).unwrap(), 1024);
+
+        // before the start fails and keeps the offset
+        assert_eq!(file.seek(SeekFrom::Start(600)).unwrap(), 600);
+        assert_eq!(
+            file.seek(SeekFrom::Current(-601)),
+            Err(FsError::InvalidOffset)
+        );
+        assert_eq!(file.seek(SeekFrom::End(-1025)), Err(FsError::InvalidOffset));
+        assert_eq!(file.read(&mut buf).unwrap(), 100);
+        assert_eq!(buf, a[600..700]);
+
+        // the start of the file is still reachable from the end
+        assert_eq!(file.seek(SeekFrom::End(-1024)).unwrap(), 0);
+        assert_eq!(file.read(&mut buf).unwrap(), 100);
+        assert_eq!(buf, a[..100]);
+    }
+
+    fn read_file(fs: &Fat16, path: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        fs.open_file(path).unwrap().read_all(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_create_and_write() {
+        let a = content(1000, 0x5a);
+        let fs = volume(&[("A.TXT", &a, &[2, 3])]);
+
+        let data = content(1300, 0x33);
+        let mut file = fs.create_file("log.txt").unwrap();
+        assert_eq!(file.write(&data[..700]).unwrap(), 700);
+        assert_eq!(file.write(&data[700..]).unwrap(), 600);
+
+        assert_eq!(fs.metadata("log.txt").unwrap().len, 1300);
+        assert_eq!(read_file(&fs, "log.txt"), data);
+        assert_eq!(read_file(&fs, "a.txt"), a);
+
+        // overwrite across a cluster boundary and grow the file at the same time
+        let mut data = data;
+        data[1000..].fill(0xee);
+        data.resize(1600, 0xee);
+        assert_eq!(file.seek(SeekFrom::Start(1000)).unwrap(), 1000);
+        assert_eq!(file.write(&data[1000..]).unwrap(), 600);
+        assert_eq!(read_file(&fs, "log.txt"), data);
+    }
+
+    #[test]
+    fn test_truncate_and_append() {
+        let a = content(1300, 0x5a);
+        let fs = volume(&[("A.TXT", &a, &[2, 4, 6])]);
+
+        let mut file = fs.create_file("a.txt").unwrap();
+        assert_eq!(fs.metadata("a.txt").unwrap().len, 0);
+        assert_eq!(file.write(b"hello").unwrap(), 5);
+
+        // the truncated clusters are free again
+        assert_eq!(fs.handle.alloc_cluster(None).unwrap(), Cluster(3));
+
+        let mut file = fs.append_file("a.txt").unwrap();
+        let tail = content(600, 0x11);
+        assert_eq!(file.write(&tail).unwrap(), 600);
+
+        let mut expected = b"hello".to_vec();
+        expected.extend_from_slice(&tail);
+        assert_eq!(read_file(&fs, "a.txt"), expected);
+
+        assert_eq!(fs.append_file("b.txt").unwrap_err(), FsError::FileNotFound);
+    }
+
+    #[test]
+    fn test_open_for_write() {
+        let a = content(1300, 0x5a);
+        let fs = volume(&[("A.TXT", &a, &[2, 4, 6])]);
+
+        // overwrites from the start without truncating
+        let mut file = fs.open_file_for_write("a.txt").unwrap();
+        let head = content(600, 0x11);
+        assert_eq!(file.write(&head).unwrap(), 600);
+
+        let mut expected = head.clone();
+        expected.extend_from_slice(&a[600..]);
+        assert_eq!(fs.metadata("a.txt").unwrap().len, 1300);
+        assert_eq!(read_file(&fs, "a.txt"), expected);
+
+        assert_eq!(
+            fs.open_file_for_write("b.txt").unwrap_err(),
+            FsError::FileNotFound
+        );
+    }
+
+    #[test]
+    fn test_write_limits() {
+        let a = content(100, 0x5a);
+        let fs = volume(&[("A.TXT", &a, &[2])]);
+
+        // opened for reading only
+        let mut file = fs.open_file("a.txt").unwrap();
+        assert_eq!(file.write(b"x"), Err(FsError::ReadOnly));
+
+        // the rest of the 61 clusters fill up
+        let data = content(61 * BLOCK_SIZE, 0x77);
+        let mut file = fs.create_file("big.bin").unwrap();
+        assert_eq!(file.write(&data).unwrap(), 60 * BLOCK_SIZE);
+        assert_eq!(file.write(&data), Err(FsError::WriteZero));
+        assert_eq!(read_file(&fs, "big.bin"), data[..60 * BLOCK_SIZE]);
     }
 }
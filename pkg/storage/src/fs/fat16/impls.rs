@@ -100,8 +100,34 @@ impl Fat16Impl {
     }
 
     pub fn name_to_entry(&self, dir: &Directory, name: &str) -> Result<DirEntry> {
+        self.locate_entry(dir, name).map(|(entry, _)| entry)
+    }
+
+    /// Find the entry `name` in `dir`, along with where it is stored
+    pub fn locate_entry(&self, dir: &Directory, name: &str) -> Result<(DirEntry, DirEntryPos)> {
         let short_name = ShortFileName::parse(name)?;
 
+        self.scan_dir(dir, |ent| {
+            ent.is_valid() && !ent.is_long_name() && ent.filename.matches(&short_name)
+        })?
+        .ok_or(FsError::FileNotFound)
+    }
+
+    /// Find a free slot for a new entry in `dir`
+    ///
+    /// Directories are not extended, a full one fails with `WriteZero`.
+    pub fn free_entry(&self, dir: &Directory) -> Result<DirEntryPos> {
+        self.scan_dir(dir, |ent| !ent.is_valid())?
+            .map(|(_, pos)| pos)
+            .ok_or(FsError::WriteZero)
+    }
+
+    /// Return the first entry of `dir` that `pred` accepts, free slots included
+    fn scan_dir(
+        &self,
+        dir: &Directory,
+        mut pred: impl FnMut(&DirEntry) -> bool,
+    ) -> Result<Option<(DirEntry, DirEntryPos)>> {
         let sectors_per_cluster = self.bpb.sectors_per_cluster() as usize;
         let root_dir_sectors = self.first_data_sector - self.first_root_dir_sector;
 
@@ -116,36 +142,118 @@ impl Fat16Impl {
                 sectors_per_cluster
             };
 
-            for sec in first_sector..first_sector + sector_cnt {
-                self.inner.read_block(sec, &mut block)?;
+            for sector in first_sector..first_sector + sector_cnt {
+                self.inner.read_block(sector, &mut block)?;
 
-                for i in 0..BLOCK_SIZE / DirEntry::LEN {
-                    let off = i * DirEntry::LEN;
-                    let end = off + DirEntry::LEN;
-                    let ent = DirEntry::parse(&block[off..end])?;
-
-                    if !ent.is_valid() || ent.is_long_name() {
-                        continue;
-                    }
-                    if ent.filename.matches(&short_name) {
-                        return Ok(ent);
+                for offset in (0..BLOCK_SIZE).step_by(DirEntry::LEN) {
+                    let ent = DirEntry::parse(&block[offset..offset + DirEntry::LEN])?;
+                    if pred(&ent) {
+                        return Ok(Some((ent, DirEntryPos { sector, offset })));
                     }
                 }
             }
 
             if cluster == Cluster::ROOT_DIR {
-                return Err(FsError::FileNotFound);
+                return Ok(None);
             }
             cluster = match self.next_cluster(cluster) {
-                Ok(Cluster::END_OF_FILE) | Err(FsError::EndOfFile) => {
-                    return Err(FsError::FileNotFound);
-                }
+                Ok(Cluster::END_OF_FILE) | Err(FsError::EndOfFile) => return Ok(None),
                 Ok(next) => next,
                 Err(e) => return Err(e),
             }
         }
     }
 
+    /// Store `entry` at `pos`
+    ///
+    /// A free slot is cleared first, the new entry does not keep the
+    /// timestamps of a deleted one.
+    pub fn write_entry(&self, pos: &DirEntryPos, entry: &DirEntry) -> Result<()> {
+        let mut block = Block::default();
+        self.inner.read_block(pos.sector, &mut block)?;
+
+        let data = &mut block.as_mut()[pos.offset..pos.offset + DirEntry::LEN];
+        let name = ShortFileName::new(&data[..11]);
+        if name.is_eod() || name.is_unused() {
+            data.fill(0);
+        }
+        entry.write_to(data);
+
+        self.inner.write_block(pos.sector, &block)
+    }
+
+    /// Number of clusters in the data area, numbered from 2
+    fn cluster_count(&self) -> usize {
+        let data_sectors = self.bpb.total_sectors() as usize - self.first_data_sector;
+        let fat_entries = self.bpb.sectors_per_fat() as usize * BLOCK_SIZE / 2;
+        core::cmp::min(
+            data_sectors / self.bpb.sectors_per_cluster() as usize,
+            fat_entries - 2,
+        )
+    }
+
+    /// Set the FAT entry of `cluster` to `value` in every copy of the FAT
+    fn set_fat_entry(&self, cluster: Cluster, value: u16) -> Result<()> {
+        // entries are 2 bytes and never cross a sector
+        let fat_offset = (cluster.0 as usize) * 2;
+        let within = fat_offset % BLOCK_SIZE;
+
+        let mut block = Block::default();
+        for fat in 0..self.bpb.fat_count() as usize {
+            let sector_index = self.fat_start
+                + fat * self.bpb.sectors_per_fat() as usize
+                + fat_offset / BLOCK_SIZE;
+            self.inner.read_block(sector_index, &mut block)?;
+            block.as_mut()[within..within + 2].copy_from_slice(&value.to_le_bytes());
+            self.inner.write_block(sector_index, &block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocate a free cluster that ends a chain, linked after `prev` if given
+    ///
+    /// Fails with `WriteZero` once the volume is full.
+    pub fn alloc_cluster(&self, prev: Option<Cluster>) -> Result<Cluster> {
+        let end = self.cluster_count() + 2;
+        let mut block = Block::default();
+        let mut loaded = usize::MAX;
+
+        for n in 2..end {
+            let fat_offset = n * 2;
+            let sector_index = self.fat_start + fat_offset / BLOCK_SIZE;
+            if sector_index != loaded {
+                self.inner.read_block(sector_index, &mut block)?;
+                loaded = sector_index;
+            }
+
+            let within = fat_offset % BLOCK_SIZE;
+            if block[within] != 0 || block[within + 1] != 0 {
+                continue;
+            }
+
+            let cluster = Cluster(n as u32);
+            self.set_fat_entry(cluster, 0xFFFF)?;
+            if let Some(prev) = prev {
+                self.set_fat_entry(prev, cluster.0 as u16)?;
+            }
+            return Ok(cluster);
+        }
+
+        Err(FsError::WriteZero)
+    }
+
+    /// Free every cluster of the chain starting at `cluster`
+    pub fn free_chain(&self, mut cluster: Cluster) -> Result<()> {
+        while cluster != Cluster::EMPTY && cluster != Cluster::END_OF_FILE {
+            let next = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, 0)?;
+            cluster = next;
+        }
+
+        Ok(())
+    }
+
     pub fn parse_path(&self, root_path: &str) -> Option<Directory> {
         let mut cur_dir = self.root_dir().ok()?;
 
@@ -251,27 +359,8 @@ impl FileSystem for Fat16 {
 
     fn open_file(&self, path: &str) -> Result<FileHandle> {
         // FIXME: open file and return a file handle
-        let (parent_path, filename) = match path.rfind('/') {
-            None => ("", path),
-            Some(idx) if idx == path.len() - 1 => {
-                return Err(FsError::NotAFile);
-            }
-            Some(idx) => (&path[..idx], &path[idx + 1..]),
-        };
-
-        let parent_dir = self
-            .handle
-            .parse_path(parent_path)
-            .ok_or(FsError::FileNotFound)?;
-
-        let entry = self.handle.name_to_entry(&parent_dir, filename)?;
-        if entry.is_directory() {
-            return Err(FsError::NotAFile);
-        }
-
-        let file = File::new(self.handle.clone(), entry.clone());
-        let metadata = (&entry).try_into().unwrap();
-        Ok(FileHandle::new(metadata, Box::new(file)))
+        let (entry, pos) = self.locate_file(path)?;
+        Ok(self.file_handle(File::new(self.handle.clone(), entry, pos, false)))
     }
 
     fn metadata(&self, path: &str) -> Result<Metadata> {
@@ -327,4 +416,89 @@ impl FileSystem for Fat16 {
             Err(e) => return Err(e),
         }
     }
+
+    fn create_file(&self, path: &str) -> Result<FileHandle> {
+        let (parent_path, filename) = split_path(path)?;
+        let dir = self
+            .handle
+            .parse_path(parent_path)
+            .ok_or(FsError::FileNotFound)?;
+
+        let (entry, pos) = match self.handle.locate_entry(&dir, filename) {
+            // an existing file is truncated
+            Ok((mut entry, pos)) => {
+                if entry.is_directory() {
+                    return Err(FsError::NotAFile);
+                }
+                if entry.attributes.contains(Attributes::READ_ONLY) {
+                    return Err(FsError::ReadOnly);
+                }
+                self.handle.free_chain(entry.cluster)?;
+                entry.cluster = Cluster::EMPTY;
+                entry.size = 0;
+                (entry, pos)
+            }
+            Err(FsError::FileNotFound) => {
+                let entry = DirEntry::new_file(ShortFileName::parse(filename)?);
+                (entry, self.handle.free_entry(&dir)?)
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.handle.write_entry(&pos, &entry)?;
+        Ok(self.file_handle(File::new(self.handle.clone(), entry, pos, true)))
+    }
+
+    fn open_file_for_write(&self, path: &str) -> Result<FileHandle> {
+        let (entry, pos) = self.locate_file(path)?;
+        if entry.attributes.contains(Attributes::READ_ONLY) {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(self.file_handle(File::new(self.handle.clone(), entry, pos, true)))
+    }
+
+    fn append_file(&self, path: &str) -> Result<FileHandle> {
+        let (entry, pos) = self.locate_file(path)?;
+        if entry.attributes.contains(Attributes::READ_ONLY) {
+            return Err(FsError::ReadOnly);
+        }
+
+        // writes start at the end, the file is not shared with other handles
+        let mut file = File::new(self.handle.clone(), entry, pos, true);
+        file.seek(SeekFrom::End(0))?;
+        Ok(self.file_handle(file))
+    }
+}
+
+impl Fat16 {
+    /// Find the file at `path`, along with where its entry is stored
+    fn locate_file(&self, path: &str) -> Result<(DirEntry, DirEntryPos)> {
+        let (parent_path, filename) = split_path(path)?;
+        let parent_dir = self
+            .handle
+            .parse_path(parent_path)
+            .ok_or(FsError::FileNotFound)?;
+
+        let (entry, pos) = self.handle.locate_entry(&parent_dir, filename)?;
+        if entry.is_directory() {
+            return Err(FsError::NotAFile);
+        }
+
+        Ok((entry, pos))
+    }
+
+    fn file_handle(&self, file: File) -> FileHandle {
+        let metadata = file.entry().into();
+        FileHandle::new(metadata, Box::new(file))
+    }
+}
+
+/// Split `path` into its parent directory and file name
+fn split_path(path: &str) -> Result<(&str, &str)> {
+    match path.rfind('/') {
+        None => Ok(("", path)),
+        Some(idx) if idx == path.len() - 1 => Err(FsError::NotAFile),
+        Some(idx) => Ok((&path[..idx], &path[idx + 1..])),
+    }
 }
@@ -2,6 +2,9 @@
 #![allow(dead_code, unused_imports)]
 #![cfg_attr(test, feature(concat_bytes))]
 #![feature(trait_alias)]
+// stable on newer toolchains, still gated on the pinned one
+#![allow(stable_features)]
+#![feature(unsigned_is_multiple_of)]
 
 #[macro_use]
 extern crate alloc;
@@ -0,0 +1,129 @@
+use num_enum::TryFromPrimitive;
+
+/// Error numbers returned by syscalls
+///
+/// A failed syscall returns the negated error number in `rax`,
+/// the values follow Linux where an equivalent exists.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum Errno {
+    /// Operation not permitted
+    NotPermitted = 1,
+    /// No such file, directory, app or object
+    NotFound = 2,
+    /// No such process
+    NoProcess = 3,
+    /// Interrupted by a signal
+    Interrupted = 4,
+    /// I/O error
+    IoError = 5,
+    /// Argument list too long
+    ArgListTooLong = 7,
+    /// Exec format error
+    NoExec = 8,
+    /// Bad file descriptor
+    BadFd = 9,
+    /// No child processes
+    NoChild = 10,
+    /// Resource temporarily unavailable
+    WouldBlock = 11,
+    /// Out of memory
+    NoMemory = 12,
+    /// Bad address
+    Fault = 14,
+    /// Object already exists
+    Exists = 17,
+    /// No such device, e.g. mapping a resource that is not a file
+    NoDevice = 19,
+    /// Not a directory
+    NotADirectory = 20,
+    /// Is a directory
+    IsADirectory = 21,
+    /// Invalid argument
+    InvalidArgument = 22,
+    /// Too many open files
+    TooManyFiles = 24,
+    /// No space left on device
+    NoSpace = 28,
+    /// Illegal seek
+    InvalidSeek = 29,
+    /// Read-only file system
+    ReadOnly = 30,
+    /// Broken pipe
+    BrokenPipe = 32,
+    /// File name too long
+    NameTooLong = 36,
+    /// Function not implemented
+    NoSys = 38,
+    /// Operation not supported
+    NotSupported = 95,
+}
+
+/// The largest error number, `rax` values in `-MAX_ERRNO..0` are errors
+pub const MAX_ERRNO: usize = 4095;
+
+pub type SyscallResult = Result<usize, Errno>;
+
+impl Errno {
+    /// Encode the error as a negative return value
+    #[inline]
+    pub fn as_ret(self) -> usize {
+        (self as usize).wrapping_neg()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Errno::NotPermitted => "operation not permitted",
+            Errno::NotFound => "no such file or directory",
+            Errno::NoProcess => "no such process",
+            Errno::Interrupted => "interrupted system call",
+            Errno::IoError => "i/o error",
+            Errno::ArgListTooLong => "argument list too long",
+            Errno::NoExec => "exec format error",
+            Errno::BadFd => "bad file descriptor",
+            Errno::NoChild => "no child processes",
+            Errno::WouldBlock => "resource temporarily unavailable",
+            Errno::NoMemory => "out of memory",
+            Errno::Fault => "bad address",
+            Errno::Exists => "already exists",
+            Errno::NoDevice => "no such device",
+            Errno::NotADirectory => "not a directory",
+            Errno::IsADirectory => "is a directory",
+            Errno::InvalidArgument => "invalid argument",
+            Errno::TooManyFiles => "too many open files",
+            Errno::NoSpace => "no space left on device",
+            Errno::InvalidSeek => "illegal seek",
+            Errno::ReadOnly => "read-only file system",
+            Errno::BrokenPipe => "broken pipe",
+            Errno::NameTooLong => "file name too long",
+            Errno::NoSys => "function not implemented",
+            Errno::NotSupported => "operation not supported",
+        }
+    }
+}
+
+impl core::fmt::Display for Errno {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Encode a syscall result into the value placed in `rax`
+#[inline]
+pub fn encode(ret: SyscallResult) -> usize {
+    match ret {
+        Ok(val) => val,
+        Err(errno) => errno.as_ret(),
+    }
+}
+
+/// Decode the value returned in `rax` into a syscall result
+#[inline]
+pub fn decode(ret: usize) -> SyscallResult {
+    let errno = ret.wrapping_neg();
+    if ret != 0 && errno <= MAX_ERRNO {
+        Err(Errno::try_from(errno).unwrap_or(Errno::InvalidArgument))
+    } else {
+        Ok(ret)
+    }
+}
@@ -1,8 +1,79 @@
 #![no_std]
 
-use num_enum::FromPrimitive;
+use num_enum::{FromPrimitive, TryFromPrimitive};
 
+pub mod errno;
 pub mod macros;
+pub mod signal;
+
+pub use errno::{Errno, SyscallResult};
+pub use signal::{SigSet, Signal};
+
+/// `WaitPid` target meaning any child process
+pub const WAIT_ANY: isize = -1;
+
+/// `WaitPid` option: return 0 instead of blocking if no child has exited
+pub const WNOHANG: usize = 1;
+
+/// `Open` flag: open for writing, reading is always allowed
+pub const O_WRONLY: usize = 0o1;
+/// `Open` flag: create the file if it does not exist
+pub const O_CREAT: usize = 0o100;
+/// `Open` flag: truncate the file to length 0
+pub const O_TRUNC: usize = 0o1000;
+/// `Open` flag: write at the end of the file
+pub const O_APPEND: usize = 0o2000;
+/// `Open`, `Dup` and `Dup2` flag: close the fd when the process calls `Exec`
+pub const O_CLOEXEC: usize = 0o2000000;
+
+/// `Mmap` and `Mprotect` protection: pages cannot be accessed
+pub const PROT_NONE: usize = 0;
+/// `Mmap` and `Mprotect` protection: pages can be read
+pub const PROT_READ: usize = 1;
+/// `Mmap` and `Mprotect` protection: pages can be written, which implies reading
+pub const PROT_WRITE: usize = 2;
+/// `Mmap` and `Mprotect` protection: pages can be executed, never with `PROT_WRITE`
+pub const PROT_EXEC: usize = 4;
+
+/// `Mmap` flag: changes are private to the process, required
+pub const MAP_PRIVATE: usize = 0x02;
+/// `Mmap` flag: map exactly at `addr`, replacing existing mappings
+pub const MAP_FIXED: usize = 0x10;
+/// `Mmap` flag: zero-filled memory not backed by a file
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// Arguments of `Mmap`
+///
+/// `addr` is a hint unless `MAP_FIXED` is set, `fd` and the page aligned
+/// `offset` select the file to copy from unless `MAP_ANONYMOUS` is set.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MmapArgs {
+    pub addr: usize,
+    pub len: usize,
+    pub prot: usize,
+    pub flags: usize,
+    pub fd: usize,
+    pub offset: usize,
+}
+
+/// The largest argument vector and environment accepted by `Spawn` and `Exec`,
+/// counting the strings, their terminators and the pointer arrays
+pub const ARG_MAX: usize = 2048;
+
+/// Argument vector and environment passed to `Spawn` and `Exec`
+///
+/// Both are packed as NUL-terminated strings, environment entries are
+/// `KEY=VALUE`. A null `argv_ptr` passes the program name as the only
+/// argument, a null `envp_ptr` inherits the caller's environment.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExecArgs {
+    pub argv_ptr: usize,
+    pub argv_len: usize,
+    pub envp_ptr: usize,
+    pub envp_len: usize,
+}
 
 #[repr(usize)]
 #[derive(Clone, Debug, FromPrimitive)]
@@ -12,7 +83,23 @@ pub enum Syscall {
 
     Time = 2,
 
+    Close = 3,
+    Seek = 8,
+    Mmap = 9,
+    Mprotect = 10,
+    Munmap = 11,
+
     Brk = 12,
+    SigAction = 13,
+    SigProcMask = 14,
+    SigReturn = 15,
+
+    Pipe = 22,
+
+    Dup = 32,
+    Dup2 = 33,
+
+    Sleep = 35,
 
     GetPid = 39,
 
@@ -22,15 +109,33 @@ pub enum Syscall {
     WaitPid = 61,
     Sem = 62,
 
+    IoPerm = 173,
+
+    Kill = 200,
+
     ListDir = 217,
     Exists = 218,
     Cat = 219,
 
+    ClockGetTime = 228,
+
+    Open = 257,
+
+    Exec = 322,
+
     ListApp = 65531,
     Stat = 65532,
-    Allocate = 65533,
-    Deallocate = 65534,
 
     #[num_enum(default)]
     Unknown = 65535,
 }
+
+/// Clocks readable with `ClockGetTime`
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum ClockId {
+    /// Wall-clock time since the Unix epoch
+    Realtime = 0,
+    /// Time since boot, never goes backwards
+    Monotonic = 1,
+}
@@ -7,8 +7,9 @@ pub fn syscall0(n: Syscall) -> usize {
     let ret: usize;
     unsafe {
         asm!(
-            "int 0x80", in("rax") n as usize,
-            lateout("rax") ret
+            "syscall", in("rax") n as usize,
+            lateout("rax") ret,
+            out("rcx") _, out("r11") _
         );
     }
     ret
@@ -20,9 +21,10 @@ pub fn syscall1(n: Syscall, arg0: usize) -> usize {
     let ret: usize;
     unsafe {
         asm!(
-            "int 0x80", in("rax") n as usize,
+            "syscall", in("rax") n as usize,
             in("rdi") arg0,
-            lateout("rax") ret
+            lateout("rax") ret,
+            out("rcx") _, out("r11") _
         );
     }
     ret
@@ -34,9 +36,10 @@ pub fn syscall2(n: Syscall, arg0: usize, arg1: usize) -> usize {
     let ret: usize;
     unsafe {
         asm!(
-            "int 0x80", in("rax") n as usize,
+            "syscall", in("rax") n as usize,
             in("rdi") arg0, in("rsi") arg1,
-            lateout("rax") ret
+            lateout("rax") ret,
+            out("rcx") _, out("r11") _
         );
     }
     ret
@@ -48,9 +51,10 @@ pub fn syscall3(n: Syscall, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let ret: usize;
     unsafe {
         asm!(
-            "int 0x80", in("rax") n as usize,
+            "syscall", in("rax") n as usize,
             in("rdi") arg0, in("rsi") arg1, in("rdx") arg2,
-            lateout("rax") ret
+            lateout("rax") ret,
+            out("rcx") _, out("r11") _
         );
     }
     ret
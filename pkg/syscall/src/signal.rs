@@ -0,0 +1,159 @@
+use num_enum::TryFromPrimitive;
+
+/// Signals that can be sent to a process
+///
+/// The numbers follow Linux, stop and continue are not supported.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, TryFromPrimitive)]
+pub enum Signal {
+    /// Hangup
+    Hup = 1,
+    /// Interrupt from the keyboard
+    Int = 2,
+    /// Quit from the keyboard
+    Quit = 3,
+    /// Illegal instruction
+    Ill = 4,
+    /// Breakpoint or single step
+    Trap = 5,
+    /// Abort
+    Abrt = 6,
+    /// Misaligned memory access
+    Bus = 7,
+    /// Arithmetic error, like a division by zero
+    Fpe = 8,
+    /// Kill, cannot be caught, blocked or ignored
+    Kill = 9,
+    /// User-defined signal 1
+    Usr1 = 10,
+    /// Invalid memory reference
+    Segv = 11,
+    /// User-defined signal 2
+    Usr2 = 12,
+    /// Write to a pipe with no readers
+    Pipe = 13,
+    /// Timer expired
+    Alrm = 14,
+    /// Termination request
+    Term = 15,
+    /// Child exited
+    Chld = 17,
+}
+
+/// What happens to a signal that has no handler installed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+}
+
+impl Signal {
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Signal::Chld => DefaultAction::Ignore,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    /// Whether a handler or mask may change how the signal is handled
+    #[inline]
+    pub fn can_catch(self) -> bool {
+        self != Signal::Kill
+    }
+
+    /// Exit code of a process terminated by the signal, as reported by shells
+    #[inline]
+    pub fn exit_code(self) -> isize {
+        128 + self as isize
+    }
+}
+
+/// `SigAction` handler restoring the default action
+pub const SIG_DFL: usize = 0;
+/// `SigAction` handler ignoring the signal
+pub const SIG_IGN: usize = 1;
+
+/// How `SigProcMask` changes the blocked set
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SigMaskHow {
+    /// Add the given signals to the blocked set
+    Block = 0,
+    /// Remove the given signals from the blocked set
+    Unblock = 1,
+    /// Replace the blocked set
+    SetMask = 2,
+}
+
+/// A set of signals, bit `n` stands for signal `n`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SigSet(u64);
+
+impl SigSet {
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    pub fn contains(&self, sig: Signal) -> bool {
+        self.0 & (1 << sig as u64) != 0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, sig: Signal) {
+        self.0 |= 1 << sig as u64;
+    }
+
+    #[inline]
+    pub fn remove(&mut self, sig: Signal) {
+        self.0 &= !(1 << sig as u64);
+    }
+
+    #[inline]
+    pub fn union(self, other: SigSet) -> SigSet {
+        Self(self.0 | other.0)
+    }
+
+    #[inline]
+    pub fn difference(self, other: SigSet) -> SigSet {
+        Self(self.0 & !other.0)
+    }
+
+    /// The lowest numbered signal in the set
+    pub fn first(&self) -> Option<Signal> {
+        let mut bits = self.0;
+        while bits != 0 {
+            let signo = bits.trailing_zeros() as usize;
+            if let Ok(sig) = Signal::try_from(signo) {
+                return Some(sig);
+            }
+            bits &= bits - 1;
+        }
+        None
+    }
+}
+
+impl From<Signal> for SigSet {
+    fn from(sig: Signal) -> Self {
+        let mut set = Self::empty();
+        set.insert(sig);
+        set
+    }
+}